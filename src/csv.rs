@@ -0,0 +1,144 @@
+use crate::value::Value;
+
+// Когда брать поле в кавычки!
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuoteStyle {
+    #[default]
+    Necessary,  // Только если внутри разделитель, кавычка или перевод строки — как в RFC 4180!
+    Always,     // Всегда — и шапку, и пустые ячейки!
+    NonNumeric, // Всё, кроме чисел — Excel сразу видит, где цифры!
+    Never,      // Никогда — поля летят как есть, разделители внутри на вашей совести!
+}
+
+// Диалект CSV — разделитель, кавычки, концы строк и всё, что любит Excel!
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvDialect {
+    pub delimiter: char,         // Разделитель полей: ',', ';' или '\t'!
+    pub quote: char,             // Кавычка — внутри поля удваивается!
+    pub quote_style: QuoteStyle, // Когда брать поле в кавычки!
+    pub line_terminator: String, // Конец записи — по RFC 4180 это \r\n!
+    pub header: bool,            // Писать ли строку с именами колонок!
+    pub bom: bool,               // Метка порядка байтов UTF-8 — Excel без неё путает кодировку!
+    pub union_header: bool,      // Шапка из ключей всех строк и в render_iter_to — ценой буфера на все строки!
+}
+
+impl Default for CsvDialect {
+    // RFC 4180: запятая, двойная кавычка по необходимости, \r\n и шапка!
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: ',',
+            quote: '"',
+            quote_style: QuoteStyle::Necessary,
+            line_terminator: "\r\n".to_string(),
+            header: true,
+            bom: false,
+            union_header: false,
+        }
+    }
+}
+
+impl CsvDialect {
+    // TSV — поля через табуляцию, остальное как в RFC 4180!
+    pub fn tsv() -> Self {
+        CsvDialect { delimiter: '\t', ..CsvDialect::default() }
+    }
+
+    // Для русского Excel — точка с запятой и BOM, иначе кириллица превратится в кракозябры!
+    pub fn excel() -> Self {
+        CsvDialect { delimiter: ';', bom: true, ..CsvDialect::default() }
+    }
+
+    // Одна запись — поля через разделитель, в конце терминатор!
+    pub(crate) fn write_record(&self, cells: &[Value], output: &mut String) {
+        for (i, cell) in cells.iter().enumerate() {
+            if i > 0 {
+                output.push(self.delimiter);
+            }
+            self.write_field(cell, output);
+        }
+        output.push_str(&self.line_terminator);
+    }
+
+    // Одно поле — в кавычках, если того требует стиль, кавычки внутри удваиваем!
+    fn write_field(&self, cell: &Value, output: &mut String) {
+        let text = cell.to_string();
+        let quoted = match self.quote_style {
+            QuoteStyle::Always => true,
+            QuoteStyle::Never => false,
+            QuoteStyle::NonNumeric => !matches!(cell, Value::Int(_) | Value::Float(_)),
+            QuoteStyle::Necessary => text.contains([self.delimiter, self.quote, '\r', '\n']),
+        };
+        if !quoted {
+            output.push_str(&text);
+            return;
+        }
+        output.push(self.quote);
+        for c in text.chars() {
+            if c == self.quote {
+                output.push(c); // "" внутри поля — одна кавычка!
+            }
+            output.push(c);
+        }
+        output.push(self.quote);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RenderOutput, YuaiRender};
+
+    // Одна запись по диалекту — строкой!
+    fn record(dialect: &CsvDialect, cells: &[Value]) -> String {
+        let mut output = String::new();
+        dialect.write_record(cells, &mut output);
+        output
+    }
+
+    fn cells() -> Vec<Value> {
+        vec![Value::from("Джек"), Value::Int(7), Value::Float(0.5), Value::Null]
+    }
+
+    // Рендер строк целиком — шапка, BOM и разделитель от RowWriter!
+    fn render(format: &str, dialect: CsvDialect) -> String {
+        let mut render = YuaiRender::new(format, None).unwrap();
+        render.set_csv_dialect(dialect);
+        let row = Value::Map([("a".to_string(), Value::from("x,y")), ("b".to_string(), Value::Int(1))].into_iter().collect());
+        match render.render_value(Value::List(vec![row])).unwrap() {
+            RenderOutput::Rendered(text) => text,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn quote_styles() {
+        let necessary = CsvDialect::default();
+        assert_eq!(record(&necessary, &cells()), "Джек,7,0.5,\r\n");
+        let always = CsvDialect { quote_style: QuoteStyle::Always, ..CsvDialect::default() };
+        assert_eq!(record(&always, &cells()), "\"Джек\",\"7\",\"0.5\",\"\"\r\n");
+        let non_numeric = CsvDialect { quote_style: QuoteStyle::NonNumeric, ..CsvDialect::default() };
+        assert_eq!(record(&non_numeric, &cells()), "\"Джек\",7,0.5,\"\"\r\n");
+        let never = CsvDialect { quote_style: QuoteStyle::Never, ..CsvDialect::default() };
+        assert_eq!(record(&never, &[Value::from("a,b"), Value::from("\"")]), "a,b,\"\r\n"); // На вашей совести!
+    }
+
+    #[test]
+    fn embedded_quotes_delimiters_and_newlines() {
+        let dialect = CsvDialect::default();
+        assert_eq!(record(&dialect, &[Value::from("say \"hi\"")]), "\"say \"\"hi\"\"\"\r\n");
+        assert_eq!(record(&dialect, &[Value::from("a,b")]), "\"a,b\"\r\n");
+        assert_eq!(record(&dialect, &[Value::from("a\nb"), Value::from("c\rd")]), "\"a\nb\",\"c\rd\"\r\n");
+        let semicolon = CsvDialect { delimiter: ';', quote: '\'', line_terminator: "\n".to_string(), ..CsvDialect::default() };
+        assert_eq!(record(&semicolon, &[Value::from("a,b"), Value::from("a;b"), Value::from("it's")]), "a,b;'a;b';'it''s'\n");
+    }
+
+    #[test]
+    fn header_bom_and_tsv() {
+        assert_eq!(render("csv", CsvDialect::default()), "a,b\r\n\"x,y\",1\r\n");
+        assert_eq!(render("csv", CsvDialect { header: false, ..CsvDialect::default() }), "\"x,y\",1\r\n");
+        assert_eq!(render("csv", CsvDialect::excel()), "\u{feff}a;b\r\nx,y;1\r\n");
+        // TSV — табуляция, даже если диалект просит запятую!
+        assert_eq!(render("tsv", CsvDialect::default()), "a\tb\r\nx,y\t1\r\n");
+        assert_eq!(render("tsv", CsvDialect { bom: true, ..CsvDialect::excel() }), "\u{feff}a\tb\r\nx,y\t1\r\n");
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, PoisonError, RwLock};
+use crate::embed_templates;
+use crate::loader::{check_name, EmbeddedLoader, FileSystemLoader, PathLoader, TemplateLoader, TemplateStamp};
+use crate::template::{parse_template, Node, Whitespace};
+use crate::RenderError;
+
+// Скомпилированный шаблон — дерево, готовое к полёту без парсинга!
+pub(crate) type Compiled = Arc<Vec<Node>>;
+
+// Режим движка — как часто сверяемся с диском!
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EngineMode {
+    #[default]
+    Production,  // Скомпилировали один раз — диск больше не трогаем!
+    Development, // Перед каждым рендером сверяем файлы — правки видны сразу!
+}
+
+// Шаблоны по умолчанию для HTML и Markdown вшиты в бинарник — рендер без шаблона летает с любым корнем!
+const BUILTIN: EmbeddedLoader = embed_templates!("templates" => "default.html", "default.md");
+
+// Откуда шаблон в ангаре — отсеки не смешиваются, include видит только загрузчик!
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Origin {
+    Loader,  // Загрузчик движка — песочница для всех имён из шаблонов!
+    Path,    // Корневой шаблон приложения, прочитанный с диска как есть!
+    Builtin, // Вшитые default.html и default.md!
+}
+
+// Ключ ангара — имя, настройки пробелов и отсек!
+type Key = (String, Whitespace, Origin);
+
+// Запись ангара — дерево и отметка файла, из которого оно собрано!
+struct Entry {
+    nodes: Compiled,              // Скомпилированный шаблон!
+    stamp: Option<TemplateStamp>, // Каким был исходник к моменту компиляции!
+}
+
+// Движок шаблонов — ангар, где карты хранятся уже разобранными!
+// Один на всё приложение: делим между рендерами и потоками через Arc.
+pub struct TemplateEngine {
+    mode: EngineMode,                                    // Продакшен или разработка!
+    loader: Box<dyn TemplateLoader>,                     // Откуда берём исходники!
+    cache: RwLock<HashMap<Key, Entry>>,                  // Скомпилированные шаблоны по имени, пробелам и отсеку!
+    missing: RwLock<HashSet<(String, Origin)>>,          // Кого нет — в продакшене запасные имена не ищем на диске повторно!
+}
+
+impl Default for TemplateEngine {
+    fn default() -> Self {
+        TemplateEngine::new()
+    }
+}
+
+impl TemplateEngine {
+    // Пустой ангар для продакшена — шаблоны из папки templates, загрузятся при первом рендере или прогреве!
+    pub fn new() -> Self {
+        TemplateEngine::with_mode(EngineMode::Production)
+    }
+
+    // Ангар для разработки — поменяли файл, и следующий рендер пересоберёт только его!
+    pub fn development() -> Self {
+        TemplateEngine::with_mode(EngineMode::Development)
+    }
+
+    // Ангар в нужном режиме — корень песочницы папка templates, а не рабочая папка с config.toml и .env!
+    pub fn with_mode(mode: EngineMode) -> Self {
+        TemplateEngine::with_loader(FileSystemLoader::new("templates"), mode)
+    }
+
+    // Ангар со своим загрузчиком — папка с корнем, память или вшитые в бинарник шаблоны!
+    pub fn with_loader(loader: impl TemplateLoader + 'static, mode: EngineMode) -> Self {
+        TemplateEngine { mode, loader: Box::new(loader), cache: RwLock::default(), missing: RwLock::default() }
+    }
+
+    // В каком режиме летим!
+    pub fn mode(&self) -> EngineMode {
+        self.mode
+    }
+
+    // Сколько шаблонов уже в ангаре!
+    pub fn len(&self) -> usize {
+        self.cache.read().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    // Ангар пуст?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Выбрасываем все скомпилированные шаблоны — следующий рендер прочитает их заново!
+    pub fn clear(&self) {
+        self.cache.write().unwrap_or_else(PoisonError::into_inner).clear();
+        self.missing.write().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+
+    // Выбрасываем один шаблон — для своего наблюдателя за файлами!
+    pub fn invalidate(&self, path: &str) {
+        self.cache.write().unwrap_or_else(PoisonError::into_inner).retain(|(cached, _, _), _| cached != path);
+        self.missing.write().unwrap_or_else(PoisonError::into_inner).retain(|(missing, _)| missing != path); // Вдруг файл появился!
+    }
+
+    // Шаблон из ангара — или загружаем и компилируем один раз! В разработке сверяем время изменения и размер.
+    pub(crate) fn load(&self, name: &str, whitespace: Whitespace) -> Result<Compiled, RenderError> {
+        check_name(name)?; // Чужие пути не пускаем ни к одному загрузчику!
+        self.compile(name, whitespace, Origin::Loader)
+    }
+
+    // Корневой шаблон из YuaiRender::new — путь задаёт приложение, а не дизайнер!
+    // Сначала ищем у загрузчика, потом читаем путь как есть (templates/page.html, /srv/site/page.html),
+    // а default.html и default.md без файлов берём из бинарника.
+    pub(crate) fn load_root(&self, name: &str, whitespace: Whitespace) -> Result<Compiled, RenderError> {
+        for origin in [Origin::Loader, Origin::Path, Origin::Builtin] {
+            if origin == Origin::Loader && check_name(name).is_err() {
+                continue; // Чужой путь загрузчику не показываем!
+            }
+            match self.compile(name, whitespace, origin) {
+                Err(RenderError::TemplateNotFound(_)) => continue, // Здесь нет — ищем в следующем отсеке!
+                result => return result,
+            }
+        }
+        Err(RenderError::TemplateNotFound(name.to_string()))
+    }
+
+    // Достаём из ангара или компилируем исходник из нужного отсека!
+    fn compile(&self, name: &str, whitespace: Whitespace, origin: Origin) -> Result<Compiled, RenderError> {
+        let loader: &dyn TemplateLoader = match origin {
+            Origin::Loader => self.loader.as_ref(),
+            Origin::Path => &PathLoader,
+            Origin::Builtin => &BUILTIN,
+        };
+        let key = (name.to_string(), whitespace, origin);
+        let stamp = match self.mode {
+            EngineMode::Production => None,
+            EngineMode::Development => match loader.modified(name) {
+                Ok(stamp) => stamp,
+                Err(e) => {
+                    // Шаблон пропал — старая версия из этого отсека больше не считается!
+                    self.cache.write().unwrap_or_else(PoisonError::into_inner).retain(|(cached, _, from), _| cached != name || *from != origin);
+                    return Err(e);
+                }
+            },
+        };
+        if let Some(entry) = self.cache.read().unwrap_or_else(PoisonError::into_inner).get(&key) {
+            if stamp.is_none() || stamp == entry.stamp {
+                return Ok(entry.nodes.clone()); // Уже разобран и не менялся — ни байта с диска!
+            }
+        }
+        let missing = (name.to_string(), origin);
+        if self.mode == EngineMode::Production && self.missing.read().unwrap_or_else(PoisonError::into_inner).contains(&missing) {
+            return Err(RenderError::TemplateNotFound(name.to_string())); // Уже искали — не нашли, диск не трогаем!
+        }
+        let source = match loader.load(name) {
+            Err(RenderError::TemplateNotFound(_)) if self.mode == EngineMode::Production => {
+                self.missing.write().unwrap_or_else(PoisonError::into_inner).insert(missing);
+                return Err(RenderError::TemplateNotFound(name.to_string()));
+            }
+            result => result?, // Берём исходник у загрузчика или шторм!
+        };
+        let nodes = Arc::new(parse_template(name, &source, whitespace)?); // Битый модуль — шторм, а не полкарты!
+        self.cache.write().unwrap_or_else(PoisonError::into_inner).insert(key, Entry { nodes: nodes.clone(), stamp });
+        Ok(nodes)
+    }
+
+    // Первый найденный шаблон из списка запасных — None, если не нашёлся ни один!
+    pub(crate) fn load_first(&self, names: &[String], whitespace: Whitespace) -> Result<Option<(String, Compiled)>, RenderError> {
+        for name in names {
+            match self.load(name, whitespace) {
+                Ok(nodes) => return Ok(Some((name.clone(), nodes))),
+                Err(RenderError::TemplateNotFound(_)) => continue, // Нет такого — пробуем следующий!
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    // Прогрев — компилируем корневой шаблон и всё, что он подтягивает через include, extends и import!
+    pub(crate) fn warm_up(&self, path: &str, whitespace: Whitespace) -> Result<(), RenderError> {
+        let nodes = self.load_root(path, whitespace)?;
+        self.warm_up_dependencies(&nodes, whitespace, &mut HashSet::from([path.to_string()]))
+    }
+
+    // Прогреваем зависимости дерева — каждый модуль один раз, циклы разберёт рендер!
+    // Промахи запасных имён и ignore missing в продакшене тоже запоминаются — после прогрева диск молчит.
+    fn warm_up_dependencies(&self, nodes: &[Node], whitespace: Whitespace, visited: &mut HashSet<String>) -> Result<(), RenderError> {
+        let mut dependencies = Vec::new();
+        collect_dependencies(nodes, &mut dependencies);
+        for (names, optional) in dependencies {
+            match self.load_first(&names, whitespace)? {
+                Some((name, nodes)) if visited.insert(name.clone()) => self.warm_up_dependencies(&nodes, whitespace, visited)?,
+                Some(_) => {} // Уже прогрет!
+                None if optional => {} // {% include ... ignore missing %} — и не надо!
+                None => return Err(RenderError::TemplateNotFound(names.join(", "))),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Все шаблоны, на которые ссылается дерево — включая вложенные в циклы и блоки!
+// Каждая зависимость — список запасных имён и можно ли обойтись без неё.
+fn collect_dependencies(nodes: &[Node], dependencies: &mut Vec<(Vec<String>, bool)>) {
+    for node in nodes {
+        match node {
+            Node::Include(spec) => dependencies.push((spec.names.clone(), spec.ignore_missing)),
+            Node::Extends(path) | Node::Import { path, .. } => dependencies.push((vec![path.clone()], false)),
+            Node::For { body, else_body, .. } => {
+                collect_dependencies(body, dependencies);
+                collect_dependencies(else_body, dependencies);
+            }
+            Node::If { branches, else_body } => {
+                branches.iter().for_each(|(_, body)| collect_dependencies(body, dependencies));
+                collect_dependencies(else_body, dependencies);
+            }
+            Node::Block { body, .. } => collect_dependencies(body, dependencies),
+            Node::Macro(def) => collect_dependencies(&def.body, dependencies),
+            Node::AutoEscape { body, .. } | Node::SetBlock { body, .. } | Node::With { body, .. } => collect_dependencies(body, dependencies),
+            Node::Text(_) | Node::Variable(_) | Node::Set(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::loader::MemoryLoader;
+
+    fn not_found(result: Result<Compiled, RenderError>) -> bool {
+        matches!(result, Err(RenderError::TemplateNotFound(_)))
+    }
+
+    fn forbidden(result: Result<Compiled, RenderError>) -> bool {
+        matches!(result, Err(RenderError::ForbiddenTemplatePath(_)))
+    }
+
+    #[test]
+    fn default_root_is_templates_dir() {
+        let engine = TemplateEngine::new();
+        assert!(engine.load("default.html", Whitespace::default()).is_ok());
+        assert!(not_found(engine.load("src/lib.rs", Whitespace::default()))); // Исходники и конфиги рядом с templates — не архив!
+        assert!(forbidden(engine.load("../src/lib.rs", Whitespace::default())));
+    }
+
+    #[test]
+    fn root_template_is_read_as_is_but_stays_out_of_reach() {
+        let engine = TemplateEngine::with_loader(MemoryLoader::new(), EngineMode::Production);
+        assert!(engine.load_root("templates/default.html", Whitespace::default()).is_ok());
+        assert!(not_found(engine.load("templates/default.html", Whitespace::default()))); // Кеш корня — не лазейка для include!
+        let absolute = std::env::current_dir().unwrap().join("templates/default.html");
+        let absolute = absolute.to_str().unwrap();
+        assert!(engine.load_root(absolute, Whitespace::default()).is_ok());
+        assert!(forbidden(engine.load(absolute, Whitespace::default())));
+    }
+
+    // Загрузчик-счётчик — сколько раз движок полез за исходником!
+    struct Counting(Arc<AtomicUsize>, MemoryLoader);
+
+    impl TemplateLoader for Counting {
+        fn load(&self, name: &str) -> Result<String, RenderError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            self.1.load(name)
+        }
+    }
+
+    #[test]
+    fn production_remembers_missing_templates() {
+        let mut templates = MemoryLoader::new();
+        templates.insert("page.html", "{% include [\"custom.html\", \"card.html\"] %}{% include \"promo.html\" ignore missing %}");
+        templates.insert("card.html", "карта");
+        let loads = Arc::new(AtomicUsize::new(0));
+        let engine = TemplateEngine::with_loader(Counting(loads.clone(), templates), EngineMode::Production);
+        engine.warm_up("page.html", Whitespace::default()).unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 4); // page, custom (нет), card, promo (нет)!
+        assert!(not_found(engine.load("custom.html", Whitespace::default())));
+        assert!(not_found(engine.load("promo.html", Whitespace::default())));
+        assert!(engine.load_first(&["custom.html".to_string(), "card.html".to_string()], Whitespace::default()).unwrap().is_some());
+        assert_eq!(loads.load(Ordering::SeqCst), 4); // После прогрева — ни одного обращения!
+        engine.invalidate("custom.html");
+        assert!(not_found(engine.load("custom.html", Whitespace::default())));
+        assert_eq!(loads.load(Ordering::SeqCst), 5); // invalidate забывает и промах!
+    }
+
+    #[test]
+    fn default_templates_are_built_in() {
+        let engine = TemplateEngine::with_loader(MemoryLoader::new(), EngineMode::Production);
+        assert!(engine.load_root("default.html", Whitespace::default()).is_ok());
+        assert!(engine.load_root("default.md", Whitespace::default()).is_ok());
+        assert!(not_found(engine.load("default.html", Whitespace::default()))); // include видит только загрузчик!
+        let mut loader = MemoryLoader::new();
+        loader.insert("default.html", "свой");
+        let engine = TemplateEngine::with_loader(loader, EngineMode::Production);
+        assert_eq!(engine.load_root("default.html", Whitespace::default()).unwrap().len(), 1); // Свой default.html важнее вшитого!
+    }
+}
@@ -0,0 +1,42 @@
+// Экранирование — щит от звёздных пиратов с <script> в трюме!
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Escape {
+    None,     // Без щита — для форматов, где экранировать нечего!
+    Html,     // HTML — прячем теги и кавычки!
+    Markdown, // Markdown — прячем разметку за обратным слэшем!
+}
+
+impl Escape {
+    // Прогоняем строку через щит!
+    pub(crate) fn apply(self, value: &str, output: &mut String) {
+        match self {
+            Escape::None => output.push_str(value),
+            Escape::Html => escape_html(value, output),
+            Escape::Markdown => escape_markdown(value, output),
+        }
+    }
+}
+
+// HTML-щит — годится и для текста, и для атрибутов в кавычках!
+fn escape_html(value: &str, output: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#x27;"),
+            _ => output.push(c),
+        }
+    }
+}
+
+// Markdown-щит — ставим обратный слэш перед символами разметки!
+fn escape_markdown(value: &str, output: &mut String) {
+    for c in value.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '~' | '&') {
+            output.push('\\');
+        }
+        output.push(c);
+    }
+}
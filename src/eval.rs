@@ -0,0 +1,640 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use crate::engine::Compiled;
+use crate::expr::{CompareOp, Expr, Location, Test};
+use crate::filters;
+use crate::template::{Macro, Node};
+use crate::value::Value;
+use crate::stream::row_fields;
+use crate::{ColumnOrder, RenderError, UndefinedBehavior, YuaiRender};
+
+// Цепочки блоков наследования — от самого младшего шаблона к базовому!
+type BlockChains = HashMap<String, Vec<Arc<Vec<Node>>>>;
+
+// Модуль макросов — свои макросы шаблона и импортированные соседи!
+#[derive(Default)]
+struct Module {
+    macros: HashMap<String, Arc<Macro>>,   // Макросы по имени: card!
+    imports: HashMap<String, Arc<Module>>, // Импорты по псевдониму: ui → components.html!
+}
+
+// Вычислитель дерева шаблона — штурман, который проходит карту узел за узлом!
+pub(crate) struct Evaluator<'a> {
+    render: &'a YuaiRender,              // Звездолёт с настройками — щит, фильтры, доверенные поля!
+    include_stack: Vec<String>,          // Цепочка включений от корня до текущего модуля — ловим циклы!
+    macro_depth: usize,                  // Сколько вызовов макросов сейчас вложены друг в друга!
+    scopes: Vec<HashMap<String, Value>>, // Стек областей видимости — rows и переменные циклов!
+    first_row: Value,                    // Первая строка — для {{ s.name }} в шапке страницы!
+    autoescape: bool,                    // Поднят ли щит прямо сейчас!
+    blocks: BlockChains,                 // Переопределённые блоки текущего документа!
+    block_stack: Vec<(String, usize)>,   // Какой блок и какой уровень цепочки рендерим — для super()!
+    module: Arc<Module>,                 // Макросы, видимые в текущем документе или макросе!
+    scope_floor: usize,                  // Ниже этой области (кроме глобальной) макрос не заглядывает!
+    location: Option<Arc<Location>>,     // Тег, чьё выражение вычисляем — для UndefinedVariable!
+    unknown: Option<String>,             // Первая неизвестная переменная текущего {{ }} — для метки Debug!
+}
+
+impl<'a> Evaluator<'a> {
+    // Новый штурман — строки данных лежат в rows, щит по настройкам звездолёта!
+    pub(crate) fn new(render: &'a YuaiRender, path: &str, rows: Vec<Value>) -> Self {
+        let rows: Vec<Value> = rows
+            .into_iter()
+            .map(|row| match row {
+                Value::Map(fields) => Value::Map(
+                    fields
+                        .into_iter()
+                        .map(|(key, value)| match value {
+                            Value::String(s) if render.trusted.contains(&key) => (key, Value::Safe(s)), // Доверенные поля сразу помечаем безопасными!
+                            value => (key, value),
+                        })
+                        .collect(),
+                ),
+                other => other,
+            })
+            .collect();
+        let first_row = rows.first().cloned().unwrap_or(Value::Null);
+        // Колонки для таблиц в шаблоне — {% for column in columns %}{{ row | attr(column) }}!
+        let columns: Vec<Value> = match &render.columns {
+            ColumnOrder::Explicit(columns) => columns.iter().map(|column| Value::from(column.as_str())).collect(),
+            ColumnOrder::Sorted => rows.iter().flat_map(|row| row_fields(row).into_keys()).collect::<BTreeSet<_>>().into_iter().map(Value::String).collect(),
+        };
+        let globals = HashMap::from([("rows".to_string(), Value::List(rows)), ("columns".to_string(), Value::List(columns))]);
+        Evaluator {
+            render,
+            include_stack: vec![path.to_string()],
+            macro_depth: 0,
+            scopes: vec![globals],
+            first_row,
+            autoescape: render.autoescape,
+            blocks: HashMap::new(),
+            block_stack: Vec::new(),
+            module: Arc::new(Module::default()),
+            scope_floor: 0,
+            location: None,
+            unknown: None,
+        }
+    }
+
+    // Рендерим целый шаблон — с {% extends %} поднимаемся к базовому, собирая блоки и макросы по пути!
+    pub(crate) fn render_document(&mut self, path: &str, nodes: Compiled, output: &mut String) -> Result<(), RenderError> {
+        let mut lineage = vec![path.to_string()]; // Родословная — ловим циклы наследования!
+        let mut levels = vec![nodes]; // Узлы каждого поколения — от младшего к базовому!
+        while let Some(parent) = levels.last().and_then(|nodes| find_extends(nodes)) {
+            if lineage.contains(&parent) {
+                lineage.push(parent);
+                return Err(RenderError::TemplateError(format!("цикл наследования: {}", lineage.join(" → "))));
+            }
+            levels.push(self.load(&parent)?);
+            lineage.push(parent);
+        }
+        let mut chains = BlockChains::new();
+        levels.iter().for_each(|nodes| collect_blocks(nodes, &mut chains)); // Блоки младшего — первыми в цепочке!
+        let mut module = Module::default();
+        for (nodes, path) in levels.iter().zip(&lineage).rev() {
+            self.collect_macros(nodes, &mut module, &mut vec![path.clone()])?; // Макросы младшего перекрывают базовые!
+        }
+        let outer_blocks = std::mem::replace(&mut self.blocks, chains);
+        let outer_stack = std::mem::take(&mut self.block_stack);
+        let outer_module = std::mem::replace(&mut self.module, Arc::new(module));
+        let base = levels.last().cloned().unwrap_or_default();
+        let result = self.render(&base, output);
+        self.blocks = outer_blocks;
+        self.block_stack = outer_stack;
+        self.module = outer_module;
+        result
+    }
+
+    // Собираем макросы верхнего уровня и импорты — вызывать их можно из любого места шаблона!
+    fn collect_macros(&self, nodes: &[Node], module: &mut Module, importing: &mut Vec<String>) -> Result<(), RenderError> {
+        for node in nodes {
+            match node {
+                Node::Macro(def) => {
+                    module.macros.insert(def.name.clone(), def.clone());
+                }
+                Node::Import { path, alias } => {
+                    importing.push(path.clone());
+                    if importing[..importing.len() - 1].contains(path) {
+                        return Err(RenderError::TemplateError(format!("цикл импорта: {}", importing.join(" → "))));
+                    }
+                    let mut imported = Module::default();
+                    let result = self.load(path).and_then(|nodes| self.collect_macros(&nodes, &mut imported, importing));
+                    importing.pop();
+                    result?;
+                    module.imports.insert(alias.clone(), Arc::new(imported));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Берём скомпилированный шаблон из движка — для include, extends и import!
+    fn load(&self, path: &str) -> Result<Compiled, RenderError> {
+        self.render.engine.load(path, self.render.whitespace())
+    }
+
+    // Рендерим узлы — каждый в своей области видимости!
+    pub(crate) fn render(&mut self, nodes: &[Node], output: &mut String) -> Result<(), RenderError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => output.push_str(text), // Просто текст — в космос!
+                Node::Variable(expr) => {
+                    let outer = self.unknown.take();
+                    let value = self.eval(expr);
+                    match (std::mem::replace(&mut self.unknown, outer), value) {
+                        (Some(name), _) => {
+                            // {{ pirate.name | upper }} на неизвестном pirate — видимая метка, а не пустота!
+                            let marker = format!("{{{{ missing: {} }}}}", name);
+                            self.render.escape().apply(&marker, output);
+                        }
+                        (None, value) => match value? {
+                            Value::Safe(value) => output.push_str(&value), // Доверенные данные — добавляем как есть!
+                            value if self.autoescape => self.render.escape().apply(&value.to_string(), output), // Чужие данные — через щит!
+                            value => output.push_str(&value.to_string()),
+                        },
+                    }
+                }
+                Node::For { item, list, body, else_body } => {
+                    let items = match self.eval(list)? {
+                        Value::Null => Vec::new(),
+                        Value::List(items) => items,
+                        Value::Map(fields) => fields.into_keys().map(Value::String).collect(), // По словарю — идём по ключам!
+                        other => vec![other], // Одиночное значение — один виток; строки режем только через | split(",")!
+                    };
+                    if items.is_empty() {
+                        self.render(else_body, output)?; // Лететь не по чему — ветка {% else %}!
+                        continue;
+                    }
+                    let parent = self.lookup("loop"); // Внешний цикл — для loop.parent!
+                    let length = items.len();
+                    for (index0, value) in items.into_iter().enumerate() {
+                        // Каждый виток — своя область видимости с бортовым журналом loop!
+                        let meta = BTreeMap::from([
+                            ("index".to_string(), Value::Int(index0 as i64 + 1)),
+                            ("index0".to_string(), Value::Int(index0 as i64)),
+                            ("revindex".to_string(), Value::Int((length - index0) as i64)),
+                            ("revindex0".to_string(), Value::Int((length - index0 - 1) as i64)),
+                            ("first".to_string(), Value::Bool(index0 == 0)),
+                            ("last".to_string(), Value::Bool(index0 + 1 == length)),
+                            ("length".to_string(), Value::Int(length as i64)),
+                            ("odd".to_string(), Value::Bool(index0 % 2 == 0)), // loop.index нечётный — для зебры!
+                            ("even".to_string(), Value::Bool(index0 % 2 == 1)),
+                            ("parent".to_string(), parent.clone()),
+                        ]);
+                        self.scopes.push(HashMap::from([(item.clone(), value), ("loop".to_string(), Value::Map(meta))]));
+                        let result = self.render(body, output);
+                        self.scopes.pop();
+                        result?;
+                    }
+                }
+                Node::If { branches, else_body } => {
+                    // Ищем первую ветку, где звёзды сошлись!
+                    let mut chosen = else_body;
+                    for (condition, body) in branches {
+                        if self.eval(condition)?.is_truthy() {
+                            chosen = body;
+                            break;
+                        }
+                    }
+                    self.render(chosen, output)?;
+                }
+                Node::AutoEscape { enabled, body } => {
+                    let outer = std::mem::replace(&mut self.autoescape, *enabled); // Меняем щит на время блока!
+                    let result = self.render(body, output);
+                    self.autoescape = outer;
+                    result?;
+                }
+                Node::Include(spec) => {
+                    // Берём первый найденный из запасных имён!
+                    let Some((file, include_nodes)) = self.render.engine.load_first(&spec.names, self.render.whitespace())? else {
+                        if spec.ignore_missing {
+                            continue; // Необязательный модуль — не нашли, и ладно!
+                        }
+                        return Err(RenderError::TemplateNotFound(spec.names.join(", ")));
+                    };
+                    // Один модуль можно включать сколько угодно раз — но не в самого себя!
+                    if self.include_stack.contains(&file) {
+                        let mut chain = self.include_stack.clone();
+                        chain.push(file);
+                        return Err(RenderError::IncludeCycle { chain });
+                    }
+                    if self.include_stack.len() > self.render.max_include_depth {
+                        let (max_depth, chain) = (self.render.max_include_depth, self.include_stack.clone());
+                        return Err(RenderError::IncludeTooDeep { max_depth, chain });
+                    }
+                    let context = match &spec.context {
+                        None => HashMap::new(),
+                        Some(expr) => match self.eval(expr)? {
+                            Value::Map(fields) => fields.into_iter().collect(),
+                            Value::Null => HashMap::new(),
+                            other => return Err(RenderError::TemplateError(format!("include with ждёт словарь, а получил '{}'", other))),
+                        },
+                    };
+                    self.include_stack.push(file.clone());
+                    let result = if spec.only {
+                        self.render_isolated(&file, include_nodes, context, output) // Модуль видит только with!
+                    } else {
+                        // Модуль видит тот же контекст плюс with, а его {% set %} наружу не протекают!
+                        self.scopes.push(context);
+                        let result = self.render_document(&file, include_nodes, output);
+                        self.scopes.pop();
+                        result
+                    };
+                    self.include_stack.pop();
+                    result?;
+                }
+                Node::Set(assignments) => {
+                    for (name, expr) in assignments {
+                        let value = self.eval(expr)?;
+                        self.assign(name, value);
+                    }
+                }
+                Node::SetBlock { name, body } => {
+                    let mut captured = String::new();
+                    self.render(body, &mut captured)?;
+                    self.assign(name, Value::Safe(captured)); // Отрендеренный кусок — уже безопасен!
+                }
+                Node::With { assignments, body } => {
+                    // Значения считаем снаружи, видны они только внутри блока!
+                    let mut scope = HashMap::new();
+                    for (name, expr) in assignments {
+                        scope.insert(name.clone(), self.eval(expr)?);
+                    }
+                    self.scopes.push(scope);
+                    let result = self.render(body, output);
+                    self.scopes.pop();
+                    result?;
+                }
+                Node::Extends(_) | Node::Macro(_) | Node::Import { .. } => {} // Уже обработали в render_document!
+                Node::Block { name, body } => {
+                    // Берём самую младшую версию блока, если шаблон кто-то наследует!
+                    let Some(chain) = self.blocks.get(name).cloned() else {
+                        self.render(body, output)?;
+                        continue;
+                    };
+                    self.block_stack.push((name.clone(), 0));
+                    let result = self.render(&chain[0], output);
+                    self.block_stack.pop();
+                    result?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // {% include ... only %} — модуль получает только свой контекст, без rows и переменных страницы!
+    fn render_isolated(&mut self, path: &str, nodes: Compiled, context: HashMap<String, Value>, output: &mut String) -> Result<(), RenderError> {
+        let outer_scopes = std::mem::replace(&mut self.scopes, vec![context]);
+        let outer_row = std::mem::replace(&mut self.first_row, Value::Null);
+        let outer_floor = std::mem::replace(&mut self.scope_floor, 0);
+        let result = self.render_document(path, nodes, output);
+        self.scopes = outer_scopes;
+        self.first_row = outer_row;
+        self.scope_floor = outer_floor;
+        result
+    }
+
+    // Вычисляем выражение — переменные, литералы, фильтры и логика!
+    fn eval(&mut self, expr: &Expr) -> Result<Value, RenderError> {
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Var(name) => match self.resolve(name) {
+                Some(value) => Ok(value),
+                None => self.undefined(name),
+            },
+            Expr::List(items) => Ok(Value::List(items.iter().map(|item| self.eval(item)).collect::<Result<_, _>>()?)),
+            Expr::Map(entries) => Ok(Value::Map(
+                entries.iter().map(|(key, value)| Ok((key.clone(), self.eval(value)?))).collect::<Result<_, RenderError>>()?,
+            )),
+            Expr::Not(expr) => Ok(Value::Bool(!self.eval(expr)?.is_truthy())),
+            Expr::And(left, right) => {
+                let left = self.eval(left)?;
+                if !left.is_truthy() {
+                    return Ok(left); // Первый маяк погас — дальше не смотрим!
+                }
+                self.eval(right)
+            }
+            Expr::Or(left, right) => {
+                let left = self.eval(left)?;
+                if left.is_truthy() {
+                    return Ok(left); // Первый маяк горит — этого хватит!
+                }
+                self.eval(right)
+            }
+            Expr::Compare(op, left, right) => {
+                let (left, right) = (self.eval(left)?, self.eval(right)?);
+                let result = match op {
+                    CompareOp::Eq => left.loose_eq(&right),
+                    CompareOp::Ne => !left.loose_eq(&right),
+                    CompareOp::Lt => left.loose_cmp(&right).is_some_and(|o| o.is_lt()),
+                    CompareOp::Le => left.loose_cmp(&right).is_some_and(|o| o.is_le()),
+                    CompareOp::Gt => left.loose_cmp(&right).is_some_and(|o| o.is_gt()),
+                    CompareOp::Ge => left.loose_cmp(&right).is_some_and(|o| o.is_ge()),
+                };
+                Ok(Value::Bool(result))
+            }
+            Expr::In { item, container, negated } => {
+                let (item, container) = (self.eval(item)?, self.eval(container)?);
+                let found = match &container {
+                    Value::List(items) => items.iter().any(|candidate| candidate.loose_eq(&item)),
+                    Value::Map(fields) => fields.contains_key(&item.to_string()), // В словаре ищем ключ!
+                    Value::Null => false,
+                    other => other.to_string().contains(&item.to_string()), // В строке ищем подстроку!
+                };
+                Ok(Value::Bool(found != *negated))
+            }
+            Expr::Filter { expr, name, args } => {
+                // default для того и нужен, чтобы прикрыть неизвестную переменную — даже в строгом режиме!
+                let value = match self.missing(expr) {
+                    Some(_) if name == "default" => Value::Null,
+                    _ => self.eval(expr)?,
+                };
+                let args = args.iter().map(|arg| self.eval(arg)).collect::<Result<Vec<_>, _>>()?;
+                if let Some(filter) = self.render.filters.get(name) {
+                    return filter(&value, &args); // Фильтр команды — у него приоритет!
+                }
+                filters::apply_builtin(name, value, &args, self.render.escape())
+                    .unwrap_or_else(|| Err(RenderError::FilterError(format!("неизвестный фильтр '{}'", name))))
+            }
+            Expr::Call { name, args, kwargs } if name == "super" && args.is_empty() && kwargs.is_empty() => self.render_super(),
+            Expr::Call { name, args, kwargs } => self.call_macro(name, args, kwargs),
+            Expr::Test { expr, test, negated } => {
+                let passed = match test {
+                    Test::Defined => self.missing(expr).is_none(),
+                    Test::Undefined => self.missing(expr).is_some(),
+                    Test::None => self.missing(expr).is_some() || self.eval(expr)? == Value::Null,
+                };
+                Ok(Value::Bool(passed != *negated))
+            }
+            Expr::Located(expr, location) => {
+                let outer = self.location.replace(location.clone());
+                let result = self.eval(expr);
+                self.location = outer;
+                result
+            }
+        }
+    }
+
+    // Переменная не найдена — пустота, метка или шторм, как настроили звездолёт!
+    fn undefined(&mut self, name: &str) -> Result<Value, RenderError> {
+        match self.render.undefined {
+            UndefinedBehavior::Lenient => return Ok(Value::Null),
+            UndefinedBehavior::Debug => {
+                self.unknown.get_or_insert_with(|| name.to_string()); // Всё выражение на ней станет меткой!
+                return Ok(Value::Null);
+            }
+            UndefinedBehavior::Strict => {}
+        }
+        let (path, line, column) = match &self.location {
+            Some(location) => (location.path.clone(), location.line, location.column),
+            None => (self.include_stack.last().cloned().unwrap_or_default(), 0, 0),
+        };
+        Err(RenderError::UndefinedVariable { name: name.to_string(), path, line, column })
+    }
+
+    // Имя неизвестной переменной, если выражение — это она и есть!
+    fn missing(&self, expr: &Expr) -> Option<String> {
+        match expr {
+            Expr::Var(name) if self.resolve(name).is_none() => Some(name.clone()),
+            Expr::Located(expr, _) => self.missing(expr),
+            _ => None,
+        }
+    }
+
+    // {{ super() }} — рендерим следующую по старшинству версию текущего блока!
+    fn render_super(&mut self) -> Result<Value, RenderError> {
+        let Some((name, level)) = self.block_stack.last().cloned() else {
+            return Err(RenderError::TemplateError("super() вызван вне {% block %}".to_string()));
+        };
+        let Some(body) = self.blocks.get(&name).and_then(|chain| chain.get(level + 1)).cloned() else {
+            return Err(RenderError::TemplateError(format!("у блока '{}' нет родительской версии для super()", name)));
+        };
+        let mut rendered = String::new();
+        self.block_stack.push((name, level + 1));
+        let unknown = self.unknown.take(); // Метки внутри блока — его забота, не {{ super() }}!
+        let result = self.render(&body, &mut rendered);
+        self.unknown = unknown;
+        self.block_stack.pop();
+        result?;
+        Ok(Value::Safe(rendered)) // Отрендеренный шаблон — уже безопасен!
+    }
+
+    // {{ card("Флот", size=3) }} или {{ ui.card(...) }} — рендерим макрос в собственной области видимости!
+    fn call_macro(&mut self, name: &str, args: &[Expr], kwargs: &[(String, Expr)]) -> Result<Value, RenderError> {
+        let (module, def) = self
+            .find_macro(name)
+            .ok_or_else(|| RenderError::TemplateError(format!("неизвестная функция или макрос '{}'", name)))?;
+        if args.len() > def.params.len() {
+            return Err(RenderError::TemplateError(format!("макрос '{}' ждёт не больше {} аргументов, а получил {}", name, def.params.len(), args.len())));
+        }
+        // Аргументы вычисляем в контексте вызывающего!
+        let mut bound = HashMap::new();
+        for ((param, _), arg) in def.params.iter().zip(args) {
+            bound.insert(param.clone(), self.eval(arg)?);
+        }
+        for (key, arg) in kwargs {
+            if !def.params.iter().any(|(param, _)| param == key) {
+                return Err(RenderError::TemplateError(format!("у макроса '{}' нет параметра '{}'", name, key)));
+            }
+            if bound.contains_key(key) {
+                return Err(RenderError::TemplateError(format!("параметр '{}' макроса '{}' передан дважды", key, name)));
+            }
+            bound.insert(key.clone(), self.eval(arg)?);
+        }
+        // Рекурсия без выхода — ошибка, а не переполнение стека!
+        if self.macro_depth >= self.render.max_macro_depth {
+            return Err(RenderError::MacroTooDeep { max_depth: self.render.max_macro_depth, name: name.to_string() });
+        }
+        // Тело видит только свои параметры, глобальные rows и макросы своего модуля!
+        let outer_module = std::mem::replace(&mut self.module, module);
+        self.scopes.push(bound);
+        let outer_floor = std::mem::replace(&mut self.scope_floor, self.scopes.len() - 1);
+        let mut rendered = String::new();
+        self.macro_depth += 1;
+        let unknown = self.unknown.take(); // Неизвестное в теле макроса помечает сам макрос, а не его вызов!
+        let result = self.render_macro(&def, &mut rendered);
+        self.unknown = unknown;
+        self.macro_depth -= 1;
+        self.scope_floor = outer_floor;
+        self.scopes.pop();
+        self.module = outer_module;
+        result?;
+        Ok(Value::Safe(rendered)) // Отрендеренный макрос — уже безопасен!
+    }
+
+    // Заполняем умолчания — они видят параметры слева — и рендерим тело макроса!
+    fn render_macro(&mut self, def: &Macro, output: &mut String) -> Result<(), RenderError> {
+        for (param, default) in &def.params {
+            if self.scopes.last().is_some_and(|scope| scope.contains_key(param)) {
+                continue;
+            }
+            let value = match default {
+                Some(default) => self.eval(default)?,
+                None => Value::Null,
+            };
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.insert(param.clone(), value);
+            }
+        }
+        self.render(&def.body, output)
+    }
+
+    // Ищем макрос: card — в текущем модуле, ui.card — через импорт ui!
+    fn find_macro(&self, name: &str) -> Option<(Arc<Module>, Arc<Macro>)> {
+        let mut module = self.module.clone();
+        let (path, macro_name) = name.rsplit_once('.').unwrap_or(("", name));
+        for alias in path.split('.').filter(|alias| !alias.is_empty()) {
+            module = module.imports.get(alias)?.clone();
+        }
+        let def = module.macros.get(macro_name)?.clone();
+        Some((module, def))
+    }
+
+    // {% set %} кладёт значение в самую внутреннюю область — виток цикла, {% with %} или макрос!
+    fn assign(&mut self, name: &str, value: Value) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), value);
+        }
+    }
+
+    // Ищем переменную — нет её, значит пустота!
+    fn lookup(&self, name: &str) -> Value {
+        self.resolve(name).unwrap_or(Value::Null)
+    }
+
+    // Ищем переменную — сначала в циклах (изнутри наружу), потом в первой строке данных!
+    fn resolve(&self, name: &str) -> Option<Value> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(depth, _)| *depth == 0 || *depth >= self.scope_floor) // Из макроса не видно чужих циклов!
+            .find_map(|(_, scope)| resolve_in_scope(scope, name))
+            .or_else(|| resolve_path(&self.first_row, name))
+    }
+}
+
+// Путь вида row.p.name в области видимости — первый сегмент это имя переменной!
+fn resolve_in_scope(scope: &HashMap<String, Value>, path: &str) -> Option<Value> {
+    if let Some(value) = scope.get(path) {
+        return Some(value.clone());
+    }
+    let (head, rest) = path.split_once('.')?;
+    resolve_path(scope.get(head)?, rest)
+}
+
+// Идём по пути внутрь значения — ключи с точками вроде "p.name" тоже находим!
+pub(crate) fn resolve_path(value: &Value, path: &str) -> Option<Value> {
+    match value {
+        Value::Map(fields) => {
+            if let Some(found) = fields.get(path) {
+                return Some(found.clone()); // Ключ целиком — "p.name" из yuaidb!
+            }
+            // Пробуем префиксы от длинного к короткому: row.p.name → "p" → "name"!
+            path.rmatch_indices('.').find_map(|(i, _)| resolve_path(fields.get(&path[..i])?, &path[i + 1..]))
+        }
+        Value::List(items) => {
+            let (index, rest) = path.split_once('.').unwrap_or((path, ""));
+            let item = items.get(index.parse::<usize>().ok()?)?;
+            if rest.is_empty() { Some(item.clone()) } else { resolve_path(item, rest) }
+        }
+        _ => None,
+    }
+}
+
+// Родитель шаблона из {% extends %}, если он есть!
+fn find_extends(nodes: &[Node]) -> Option<String> {
+    nodes.iter().find_map(|node| match node {
+        Node::Extends(parent) => Some(parent.clone()),
+        _ => None,
+    })
+}
+
+// Собираем все блоки шаблона, включая вложенные, в хвосты цепочек!
+fn collect_blocks(nodes: &[Node], chains: &mut BlockChains) {
+    for node in nodes {
+        match node {
+            Node::Block { name, body } => {
+                chains.entry(name.clone()).or_default().push(body.clone());
+                collect_blocks(body, chains);
+            }
+            Node::For { body, else_body, .. } => {
+                collect_blocks(body, chains);
+                collect_blocks(else_body, chains);
+            }
+            Node::If { branches, else_body } => {
+                branches.iter().for_each(|(_, body)| collect_blocks(body, chains));
+                collect_blocks(else_body, chains);
+            }
+            Node::AutoEscape { body, .. } | Node::SetBlock { body, .. } | Node::With { body, .. } => collect_blocks(body, chains),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EngineMode, MemoryLoader, RenderError, RenderOutput, TemplateEngine, YuaiRender};
+    use crate::value::Value;
+    use std::sync::Arc;
+
+    // Рендерим page.html со складом шаблонов в памяти!
+    fn render(templates: &[(&str, &str)], rows: serde_json::Value) -> Result<String, RenderError> {
+        let mut loader = MemoryLoader::new();
+        templates.iter().for_each(|(name, source)| loader.insert(name, source));
+        let engine = Arc::new(TemplateEngine::with_loader(loader, EngineMode::Production));
+        match YuaiRender::with_engine("html", Some("page.html"), engine)?.render_value(Value::from(rows))? {
+            RenderOutput::Rendered(text) => Ok(text),
+            _ => unreachable!(),
+        }
+    }
+
+    fn page(source: &str) -> String {
+        render(&[("page.html", source)], serde_json::json!([{"name": "a", "tags": [1, 2]}, {"name": "b", "tags": []}])).unwrap()
+    }
+
+    #[test]
+    fn nested_for_in_if_in_for() {
+        let source = "{% for row in rows %}{% if row.tags %}{% for tag in row.tags %}{{ row.name }}{{ tag }}{{ loop.parent.index }};{% endfor %}{% else %}{{ row.name }}-;{% endif %}{% endfor %}";
+        assert_eq!(page(source), "a11;a21;b-;");
+    }
+
+    #[test]
+    fn set_scoping() {
+        assert_eq!(page("{% set x = 1 %}{% for row in rows %}{% set x = row.name %}{{ x }}{% endfor %}{{ x }}"), "ab1");
+        assert_eq!(page("{% with y = 3 %}{% set z = 4 %}{{ y }}{{ z }}{% endwith %}[{{ y }}{{ z }}]"), "34[]");
+        assert_eq!(page("{% set card %}<b>{{ rows | length }}</b>{% endset %}{{ card }}"), "<b>2</b>"); // Захват — уже безопасен!
+        assert_eq!(page("{% macro m() %}[{{ row }}]{% endmacro %}{% for row in rows %}{{ m() }}{% endfor %}"), "[][]"); // Переменные цикла в макрос не протекают!
+    }
+
+    #[test]
+    fn super_chains() {
+        let templates = [
+            ("base.html", "[{% block c %}base{% endblock %}|{% block d %}d{% endblock %}]"),
+            ("mid.html", "{% extends \"base.html\" %}{% block c %}mid+{{ super() }}{% endblock %}"),
+            ("page.html", "{% extends \"mid.html\" %}{% block c %}page+{{ super() }}{% endblock %}"),
+        ];
+        assert_eq!(render(&templates, serde_json::Value::Null).unwrap(), "[page+mid+base|d]");
+        let error = render(&[("page.html", "{{ super() }}")], serde_json::Value::Null);
+        assert!(matches!(error, Err(RenderError::TemplateError(_))));
+    }
+
+    #[test]
+    fn include_cycle() {
+        let templates = [("page.html", "{% include \"a.html\" %}"), ("a.html", "a{% include \"b.html\" %}"), ("b.html", "b{% include \"a.html\" %}")];
+        match render(&templates, serde_json::Value::Null) {
+            Err(RenderError::IncludeCycle { chain }) => assert_eq!(chain, ["page.html", "a.html", "b.html", "a.html"]),
+            other => panic!("ждали IncludeCycle, а получили {:?}", other),
+        }
+        // Один модуль дважды — не цикл!
+        let templates = [("page.html", "{% include \"card.html\" %}{% include \"card.html\" %}"), ("card.html", "[]")];
+        assert_eq!(render(&templates, serde_json::Value::Null).unwrap(), "[][]");
+    }
+
+    #[test]
+    fn autoescape_and_macro_recursion() {
+        assert_eq!(page("{{ '<b>' }}{{ '<b>' | safe }}"), "&lt;b&gt;<b>");
+        let error = render(&[("page.html", "{% macro f(n) %}{{ f(n) }}{% endmacro %}{{ f(1) }}")], serde_json::Value::Null);
+        assert!(matches!(error, Err(RenderError::MacroTooDeep { .. })));
+    }
+}
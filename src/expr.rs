@@ -0,0 +1,525 @@
+use std::fmt;
+use std::sync::Arc;
+use crate::value::Value;
+
+// Выражение в шаблоне — маленькая звёздная формула!
+#[derive(Debug, Clone)]
+pub(crate) enum Expr {
+    Literal(Value),           // Литерал — "строка", число, true, false или none!
+    Var(String),              // Переменная — ищем в контексте!
+    List(Vec<Expr>),          // Список [a, "b", 3] — для проверок через in!
+    Map(Vec<(String, Expr)>), // Словарь {"title": p.name, size: 2} — контекст для include!
+    Filter {
+        expr: Box<Expr>, // Что фильтруем!
+        name: String,    // Имя фильтра!
+        args: Vec<Expr>, // Аргументы фильтра — тоже выражения!
+    },
+    Call {
+        name: String,                // Имя функции — super() или макрос вроде ui.card!
+        args: Vec<Expr>,             // Позиционные аргументы вызова!
+        kwargs: Kwargs,              // Именованные аргументы: title="Флот"!
+    },
+    Not(Box<Expr>),                           // not x — переворачиваем звёзды!
+    And(Box<Expr>, Box<Expr>),                // a and b — оба маяка горят!
+    Or(Box<Expr>, Box<Expr>),                 // a or b — хватит одного маяка!
+    Compare(CompareOp, Box<Expr>, Box<Expr>), // a == b, a < b — сверяем курсы!
+    In {
+        item: Box<Expr>,      // Что ищем!
+        container: Box<Expr>, // Где ищем — список или строка!
+        negated: bool,        // not in — ищем отсутствие!
+    },
+    Test {
+        expr: Box<Expr>, // Что проверяем!
+        test: Test,      // Какой проверкой!
+        negated: bool,   // is not — проверка наоборот!
+    },
+    Located(Box<Expr>, Arc<Location>), // Выражение с координатами тега — для ошибок во время рендера!
+}
+
+// Проверки после is — x is defined, x is not none!
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Test {
+    Defined,   // Переменная есть в контексте!
+    Undefined, // Переменной нет!
+    None,      // Значение пустое — null или none!
+}
+
+// Где выражение записано в шаблоне!
+#[derive(Debug)]
+pub(crate) struct Location {
+    pub(crate) path: String,  // Шаблон!
+    pub(crate) line: usize,   // Строка — с единицы!
+    pub(crate) column: usize, // Колонка — с единицы!
+}
+
+// Именованные аргументы вызова — имя и выражение!
+pub(crate) type Kwargs = Vec<(String, Expr)>;
+
+// Операторы сравнения — звёздные весы!
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CompareOp {
+    Eq, // ==
+    Ne, // !=
+    Lt, // <
+    Le, // <=
+    Gt, // >
+    Ge, // >=
+}
+
+// Лексемы выражения — атомы звёздной формулы!
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Name(String),  // Имя — p.name, upper, row, and, or, not, in!
+    Str(String),   // Строка в кавычках!
+    Int(i64),      // Целое число!
+    Float(f64),    // Дробное число!
+    Pipe,          // | — труба фильтров!
+    LParen,        // (
+    RParen,        // )
+    LBracket,      // [
+    RBracket,      // ]
+    LBrace,        // {
+    RBrace,        // }
+    Colon,         // :
+    Comma,         // ,
+    Assign,        // = — для именованных аргументов!
+    Op(CompareOp), // ==, !=, <, <=, >, >=
+}
+
+// Лексема словами — для понятных подсказок!
+impl fmt::Display for Lexeme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lexeme::Name(name) => f.write_str(name),
+            Lexeme::Str(s) => write!(f, "\"{}\"", s),
+            Lexeme::Int(i) => write!(f, "{}", i),
+            Lexeme::Float(x) => write!(f, "{}", x),
+            Lexeme::Pipe => f.write_str("|"),
+            Lexeme::LParen => f.write_str("("),
+            Lexeme::RParen => f.write_str(")"),
+            Lexeme::LBracket => f.write_str("["),
+            Lexeme::RBracket => f.write_str("]"),
+            Lexeme::LBrace => f.write_str("{"),
+            Lexeme::RBrace => f.write_str("}"),
+            Lexeme::Colon => f.write_str(":"),
+            Lexeme::Comma => f.write_str(","),
+            Lexeme::Assign => f.write_str("="),
+            Lexeme::Op(op) => f.write_str(match op {
+                CompareOp::Eq => "==",
+                CompareOp::Ne => "!=",
+                CompareOp::Lt => "<",
+                CompareOp::Le => "<=",
+                CompareOp::Gt => ">",
+                CompareOp::Ge => ">=",
+            }),
+        }
+    }
+}
+
+// Разбираем выражение целиком — ошибка возвращается подсказкой для TemplateSyntax!
+pub(crate) fn parse_expr(source: &str) -> Result<Expr, String> {
+    let lexemes = lex(source)?;
+    let mut parser = ExprParser { lexemes, pos: 0 };
+    let expr = parser.parse_or()?;
+    if let Some(extra) = parser.peek() {
+        return Err(format!("лишнее в выражении: '{}'", extra));
+    }
+    Ok(expr)
+}
+
+// Присваивания через запятую: speed = s.speed, name = p.name | upper — для {% set %} и {% with %}!
+pub(crate) fn parse_assignments(source: &str) -> Result<Kwargs, String> {
+    let lexemes = lex(source)?;
+    let mut parser = ExprParser { lexemes, pos: 0 };
+    let mut assignments = Kwargs::new();
+    loop {
+        let name = match (parser.next(), parser.next()) {
+            (Some(Lexeme::Name(name)), Some(Lexeme::Assign)) if !name.contains('.') => name,
+            _ => return Err("слева от = нужно простое имя".to_string()),
+        };
+        assignments.push((name, parser.parse_or()?));
+        match parser.next() {
+            Some(Lexeme::Comma) => continue,
+            None => return Ok(assignments),
+            Some(extra) => return Err(format!("лишнее в выражении: '{}'", extra)),
+        }
+    }
+}
+
+// Хвост {% include %} — что включаем и с каким контекстом!
+#[derive(Debug)]
+pub(crate) struct IncludeSpec {
+    pub(crate) names: Vec<String>,    // Имена шаблонов — берём первый найденный!
+    pub(crate) ignore_missing: bool,  // ignore missing — не нашли, и ладно!
+    pub(crate) context: Option<Expr>, // with {...} — свои переменные для модуля!
+    pub(crate) only: bool,            // only — модуль не видит ничего, кроме with!
+}
+
+// Разбираем "card.html" или ["custom.html", "card.html"], затем ignore missing, with {...} и only!
+// name — имя без кавычек из старого синтаксиса {% include header.html %}, тогда source — только модификаторы.
+pub(crate) fn parse_include(name: Option<&str>, source: &str) -> Result<IncludeSpec, String> {
+    let lexemes = lex(source)?;
+    let mut parser = ExprParser { lexemes, pos: 0 };
+    let names = match name {
+        Some(name) => vec![name.to_string()],
+        None => match parser.parse_primary()? {
+            Expr::Literal(Value::String(name)) => vec![name],
+            Expr::List(items) if !items.is_empty() => items
+                .into_iter()
+                .map(|item| match item {
+                    Expr::Literal(Value::String(name)) => Ok(name),
+                    _ => Err("в списке шаблонов ждём только строки в кавычках".to_string()),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err("какой шаблон включать? Укажите путь в кавычках или список путей!".to_string()),
+        },
+    };
+    let ignore_missing = parser.eat_keyword("ignore");
+    if ignore_missing && !parser.eat_keyword("missing") {
+        return Err("после ignore ждём missing".to_string());
+    }
+    let context = if parser.eat_keyword("with") { Some(parser.parse_or()?) } else { None };
+    let only = parser.eat_keyword("only");
+    if let Some(extra) = parser.peek() {
+        return Err(format!("лишнее в include: '{}' — ждём ignore missing, with {{...}} и only", extra));
+    }
+    Ok(IncludeSpec { names, ignore_missing, context, only })
+}
+
+// Режем строку на лексемы!
+fn lex(source: &str) -> Result<Vec<Lexeme>, String> {
+    let mut lexemes = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '|' => {
+                chars.next();
+                lexemes.push(Lexeme::Pipe);
+            }
+            '(' => {
+                chars.next();
+                lexemes.push(Lexeme::LParen);
+            }
+            ')' => {
+                chars.next();
+                lexemes.push(Lexeme::RParen);
+            }
+            '[' => {
+                chars.next();
+                lexemes.push(Lexeme::LBracket);
+            }
+            ']' => {
+                chars.next();
+                lexemes.push(Lexeme::RBracket);
+            }
+            ',' => {
+                chars.next();
+                lexemes.push(Lexeme::Comma);
+            }
+            '{' => {
+                chars.next();
+                lexemes.push(Lexeme::LBrace);
+            }
+            '}' => {
+                chars.next();
+                lexemes.push(Lexeme::RBrace);
+            }
+            ':' => {
+                chars.next();
+                lexemes.push(Lexeme::Colon);
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let with_eq = chars.next_if(|&(_, ch)| ch == '=').is_some();
+                let op = match (c, with_eq) {
+                    ('=', false) => {
+                        lexemes.push(Lexeme::Assign); // Одиночный = — только для name=value в вызовах!
+                        continue;
+                    }
+                    ('=', true) => CompareOp::Eq,
+                    ('!', true) => CompareOp::Ne,
+                    ('<', false) => CompareOp::Lt,
+                    ('<', true) => CompareOp::Le,
+                    ('>', false) => CompareOp::Gt,
+                    ('>', true) => CompareOp::Ge,
+                    _ => return Err(format!("непонятный оператор '{}' — может, '{}='?", c, c)),
+                };
+                lexemes.push(Lexeme::Op(op));
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut text = String::new();
+                let mut closed = false;
+                while let Some((_, ch)) = chars.next() {
+                    match ch {
+                        ch if ch == c => {
+                            closed = true;
+                            break;
+                        }
+                        '\\' => match chars.next() {
+                            Some((_, 'n')) => text.push('\n'),
+                            Some((_, 't')) => text.push('\t'),
+                            Some((_, other)) => text.push(other),
+                            None => break,
+                        },
+                        ch => text.push(ch),
+                    }
+                }
+                if !closed {
+                    return Err("строка не закрыта — потеряли кавычку!".to_string());
+                }
+                lexemes.push(Lexeme::Str(text));
+            }
+            c if c.is_ascii_digit() || (c == '-' && source[start + 1..].starts_with(|d: char| d.is_ascii_digit())) => {
+                chars.next();
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, ch)) = chars.peek() {
+                    if ch.is_ascii_digit() || ch == '.' {
+                        end = i + ch.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let number = &source[start..end];
+                let lexeme = if number.contains('.') {
+                    number.parse().map(Lexeme::Float)
+                        .map_err(|_| format!("странное число '{}'", number))?
+                } else {
+                    number.parse().map(Lexeme::Int)
+                        .map_err(|_| format!("странное число '{}'", number))?
+                };
+                lexemes.push(lexeme);
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start;
+                while let Some(&(i, ch)) = chars.peek() {
+                    if ch.is_alphanumeric() || ch == '_' || ch == '.' {
+                        end = i + ch.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                lexemes.push(Lexeme::Name(source[start..end].to_string()));
+            }
+            other => return Err(format!("непонятный символ '{}' в выражении", other)),
+        }
+    }
+    Ok(lexemes)
+}
+
+// Парсер выражений — штурман звёздных формул!
+struct ExprParser {
+    lexemes: Vec<Lexeme>, // Все лексемы!
+    pos: usize,           // Где мы сейчас!
+}
+
+impl ExprParser {
+    // Подглядываем следующую лексему!
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.pos)
+    }
+
+    // Забираем следующую лексему!
+    fn next(&mut self) -> Option<Lexeme> {
+        let lexeme = self.lexemes.get(self.pos).cloned();
+        self.pos += 1;
+        lexeme
+    }
+
+    // Забираем ключевое слово, если оно следующее!
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Lexeme::Name(name)) if name == keyword) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    // a or b — самый слабый оператор!
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.eat_keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    // a and b
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_not()?;
+        while self.eat_keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    // not a
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_compare()
+    }
+
+    // a == b, a < b, a in b, a not in b
+    fn parse_compare(&mut self) -> Result<Expr, String> {
+        let left = self.parse_filtered()?;
+        if self.peek() == Some(&Lexeme::Assign) {
+            return Err("одиночный '=' — для сравнения нужен '=='".to_string());
+        }
+        if let Some(Lexeme::Op(op)) = self.peek() {
+            let op = *op;
+            self.next();
+            let right = self.parse_filtered()?;
+            return Ok(Expr::Compare(op, Box::new(left), Box::new(right)));
+        }
+        let negated = matches!(
+            (self.lexemes.get(self.pos), self.lexemes.get(self.pos + 1)),
+            (Some(Lexeme::Name(not)), Some(Lexeme::Name(kw_in))) if not == "not" && kw_in == "in"
+        );
+        if negated {
+            self.pos += 1;
+        }
+        if self.eat_keyword("in") {
+            let container = self.parse_filtered()?;
+            return Ok(Expr::In { item: Box::new(left), container: Box::new(container), negated });
+        }
+        if self.eat_keyword("is") {
+            let negated = self.eat_keyword("not");
+            let test = match self.next() {
+                Some(Lexeme::Name(name)) if name == "defined" => Test::Defined,
+                Some(Lexeme::Name(name)) if name == "undefined" => Test::Undefined,
+                Some(Lexeme::Name(name)) if name == "none" || name == "null" => Test::None,
+                Some(other) => return Err(format!("неизвестная проверка '{}' — знаем defined, undefined, none", other)),
+                None => return Err("после is ждём проверку: defined, undefined или none".to_string()),
+            };
+            return Ok(Expr::Test { expr: Box::new(left), test, negated });
+        }
+        Ok(left)
+    }
+
+    // Значение с цепочкой фильтров: value | upper | truncate(20)
+    fn parse_filtered(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        while self.peek() == Some(&Lexeme::Pipe) {
+            self.next();
+            let Some(Lexeme::Name(name)) = self.next() else {
+                return Err("после | ждём имя фильтра!".to_string());
+            };
+            let mut args = Vec::new();
+            if self.peek() == Some(&Lexeme::LParen) {
+                self.next();
+                let kwargs;
+                (args, kwargs) = self.parse_args(&name)?;
+                if !kwargs.is_empty() {
+                    return Err(format!("фильтр '{}' не понимает именованных аргументов", name));
+                }
+            }
+            expr = Expr::Filter { expr: Box::new(expr), name, args };
+        }
+        Ok(expr)
+    }
+
+    // Аргументы в скобках — открывающая ( уже съедена! Сначала позиционные, потом name=value!
+    fn parse_args(&mut self, name: &str) -> Result<(Vec<Expr>, Kwargs), String> {
+        let mut args = Vec::new();
+        let mut kwargs = Kwargs::new();
+        if self.peek() == Some(&Lexeme::RParen) {
+            self.next();
+            return Ok((args, kwargs));
+        }
+        loop {
+            match (self.lexemes.get(self.pos), self.lexemes.get(self.pos + 1)) {
+                (Some(Lexeme::Name(key)), Some(Lexeme::Assign)) => {
+                    let key = key.clone();
+                    if kwargs.iter().any(|(k, _)| *k == key) {
+                        return Err(format!("аргумент '{}' передан в '{}' дважды", key, name));
+                    }
+                    self.pos += 2;
+                    kwargs.push((key, self.parse_or()?));
+                }
+                _ if !kwargs.is_empty() => {
+                    return Err(format!("в '{}' позиционные аргументы идут до именованных", name));
+                }
+                _ => args.push(self.parse_or()?),
+            }
+            match self.next() {
+                Some(Lexeme::Comma) => continue,
+                Some(Lexeme::RParen) => return Ok((args, kwargs)),
+                _ => return Err(format!("аргументы '{}' не закрыты — нужна )", name)),
+            }
+        }
+    }
+
+    // Простейшее выражение — имя, литерал или скобки!
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Lexeme::Name(name)) if self.peek() == Some(&Lexeme::LParen) => {
+                self.next();
+                let (args, kwargs) = self.parse_args(&name)?;
+                Ok(Expr::Call { name, args, kwargs })
+            }
+            Some(Lexeme::Name(name)) => Ok(match name.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "none" | "null" => Expr::Literal(Value::Null),
+                _ => Expr::Var(name),
+            }),
+            Some(Lexeme::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Lexeme::Int(i)) => Ok(Expr::Literal(Value::Int(i))),
+            Some(Lexeme::Float(f)) => Ok(Expr::Literal(Value::Float(f))),
+            Some(Lexeme::LBracket) => {
+                let mut items = Vec::new();
+                if self.peek() == Some(&Lexeme::RBracket) {
+                    self.next();
+                    return Ok(Expr::List(items));
+                }
+                loop {
+                    items.push(self.parse_or()?);
+                    match self.next() {
+                        Some(Lexeme::Comma) => continue,
+                        Some(Lexeme::RBracket) => return Ok(Expr::List(items)),
+                        _ => return Err("список не закрыт — нужна ]".to_string()),
+                    }
+                }
+            }
+            Some(Lexeme::LBrace) => {
+                let mut entries = Vec::new();
+                if self.peek() == Some(&Lexeme::RBrace) {
+                    self.next();
+                    return Ok(Expr::Map(entries));
+                }
+                loop {
+                    let key = match self.next() {
+                        Some(Lexeme::Str(key)) => key,
+                        Some(Lexeme::Name(key)) if !key.contains('.') => key, // Ключ без кавычек — как в JS!
+                        _ => return Err("ключ словаря — строка или имя!".to_string()),
+                    };
+                    if self.next() != Some(Lexeme::Colon) {
+                        return Err(format!("после ключа '{}' ждём :", key));
+                    }
+                    entries.push((key, self.parse_or()?));
+                    match self.next() {
+                        Some(Lexeme::Comma) => continue,
+                        Some(Lexeme::RBrace) => return Ok(Expr::Map(entries)),
+                        _ => return Err("словарь не закрыт — нужна }".to_string()),
+                    }
+                }
+            }
+            Some(Lexeme::LParen) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Lexeme::RParen) => Ok(expr),
+                    _ => Err("скобка не закрыта — нужна )".to_string()),
+                }
+            }
+            Some(other) => Err(format!("неожиданное '{}' в выражении", other)),
+            None => Err("выражение оборвалось на полпути!".to_string()),
+        }
+    }
+}
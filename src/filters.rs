@@ -0,0 +1,279 @@
+use std::fmt::Write as _;
+use std::time::SystemTime;
+use crate::escape::Escape;
+use crate::eval::resolve_path;
+use crate::value::{unix_seconds, Value};
+use crate::RenderError;
+
+// Пользовательский фильтр — значение и аргументы на входе, новое значение на выходе!
+pub type FilterFn = dyn Fn(&Value, &[Value]) -> Result<Value, RenderError> + Send + Sync;
+
+// Встроенные фильтры — бортовой арсенал! None — такого фильтра у нас нет.
+pub(crate) fn apply_builtin(name: &str, value: Value, args: &[Value], escape: Escape) -> Option<Result<Value, RenderError>> {
+    let result = match name {
+        "upper" => Ok(map_str(value, |s| s.to_uppercase())),
+        "lower" => Ok(map_str(value, |s| s.to_lowercase())),
+        "title" => Ok(map_str(value, title)),
+        "trim" => Ok(map_str(value, |s| s.trim().to_string())),
+        "truncate" => {
+            let length = arg_usize(name, args, 0, 255);
+            let end = arg_str(args, 1, "...");
+            length.map(|length| {
+                map_str(value, |s| {
+                    if s.chars().count() <= length {
+                        return s.to_string();
+                    }
+                    let mut cut: String = s.chars().take(length).collect();
+                    cut.truncate(cut.trim_end().len());
+                    cut + &end
+                })
+            })
+        }
+        "default" => Ok(if value.is_empty() { args.first().cloned().unwrap_or(Value::String(String::new())) } else { value }),
+        "replace" => {
+            let from = arg_str(args, 0, "");
+            let to = arg_str(args, 1, "");
+            Ok(if from.is_empty() { value } else { map_str(value, |s| s.replace(&from, &to)) })
+        }
+        "length" => Ok(Value::Int(match &value {
+            Value::Null => 0,
+            Value::List(items) => items.len() as i64,
+            Value::Map(fields) => fields.len() as i64,
+            Value::Bytes(bytes) => bytes.len() as i64,
+            other => other.to_string().chars().count() as i64,
+        })),
+        "attr" => Ok(resolve_path(&value, &arg_str(args, 0, "")).unwrap_or(Value::Null)), // Поле по имени из переменной!
+        "join" => {
+            let separator = arg_str(args, 0, "");
+            Ok(match value {
+                Value::List(items) => Value::String(items.iter().map(Value::to_string).collect::<Vec<_>>().join(&separator)),
+                other => other,
+            })
+        }
+        "split" => {
+            let separator = arg_str(args, 0, ",");
+            Ok(match value {
+                Value::Null => Value::List(Vec::new()),
+                Value::List(items) => Value::List(items),
+                other if separator.is_empty() => Value::List(other.to_string().chars().map(|c| Value::String(c.to_string())).collect()),
+                other => Value::List(other.to_string().split(separator.as_str()).map(Value::from).collect()),
+            })
+        }
+        "first" => Ok(match value {
+            Value::List(items) => items.into_iter().next().unwrap_or(Value::Null),
+            other => other.to_string().chars().next().map(|c| Value::String(c.to_string())).unwrap_or(Value::Null),
+        }),
+        "last" => Ok(match value {
+            Value::List(items) => items.into_iter().last().unwrap_or(Value::Null),
+            other => other.to_string().chars().last().map(|c| Value::String(c.to_string())).unwrap_or(Value::Null),
+        }),
+        "safe" => Ok(match value {
+            Value::Safe(s) => Value::Safe(s),
+            other => Value::Safe(other.to_string()),
+        }),
+        "escape" | "e" => Ok(match value {
+            Value::Safe(s) => Value::Safe(s), // Уже доверенное — второй раз не экранируем!
+            other => {
+                let escape = if escape == Escape::None { Escape::Html } else { escape };
+                let mut escaped = String::new();
+                escape.apply(&other.to_string(), &mut escaped);
+                Value::Safe(escaped)
+            }
+        }),
+        "urlencode" => Ok(Value::String(urlencode(&value.to_string()))),
+        "json" => serde_json::to_string(&value.to_json())
+            .map(|json| Value::Safe(script_safe_json(&json))) // Опасные символы уже спрятаны — щит не нужен!
+            .map_err(|e| RenderError::FilterError(format!("фильтр 'json': {}", e))),
+        "date" => {
+            let format = arg_str(args, 0, "%d.%m.%Y");
+            parse_datetime(&value)
+                .map(|dt| Value::String(dt.format(&format)))
+                .ok_or_else(|| RenderError::FilterError(format!("фильтр 'date': не похоже на дату '{}'", value)))
+        }
+        "number" => {
+            let decimals = arg_usize(name, args, 0, 0);
+            let thousands = arg_str(args, 1, " ");
+            let point = arg_str(args, 2, ".");
+            match (decimals, value.as_f64()) {
+                (Ok(decimals), Some(number)) => Ok(Value::String(format_number(number, decimals, &thousands, &point))),
+                (Err(e), _) => Err(e),
+                (_, None) => Err(RenderError::FilterError(format!("фильтр 'number': не число '{}'", value))),
+            }
+        }
+        _ => return None, // Такого фильтра в арсенале нет!
+    };
+    Some(result)
+}
+
+// Меняем строку, сохраняя пометку о доверии!
+fn map_str(value: Value, f: impl Fn(&str) -> String) -> Value {
+    match value {
+        Value::Safe(s) => Value::Safe(f(&s)),
+        other => Value::String(f(&other.to_string())),
+    }
+}
+
+// Строковый аргумент или значение по умолчанию!
+fn arg_str(args: &[Value], index: usize, default: &str) -> String {
+    args.get(index).map(Value::to_string).unwrap_or_else(|| default.to_string())
+}
+
+// Числовой аргумент — отрицательные и нечисла не принимаем!
+fn arg_usize(filter: &str, args: &[Value], index: usize, default: usize) -> Result<usize, RenderError> {
+    match args.get(index) {
+        None => Ok(default),
+        Some(arg) => arg
+            .as_f64()
+            .filter(|n| *n >= 0.0)
+            .map(|n| n as usize)
+            .ok_or_else(|| RenderError::FilterError(format!("фильтр '{}': аргумент {} должен быть неотрицательным числом, а не '{}'", filter, index + 1, arg))),
+    }
+}
+
+// Каждое слово — с большой буквы!
+fn title(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut word_start = true;
+    for c in s.chars() {
+        if c.is_alphanumeric() {
+            if word_start {
+                result.extend(c.to_uppercase());
+            } else {
+                result.extend(c.to_lowercase());
+            }
+            word_start = false;
+        } else {
+            result.push(c);
+            word_start = true;
+        }
+    }
+    result
+}
+
+// Кодируем для URL — всё, кроме безопасных символов, в %XX!
+fn urlencode(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => result.push(byte as char),
+            _ => {
+                let _ = write!(result, "%{:02X}", byte);
+            }
+        }
+    }
+    result
+}
+
+// JSON, который не закроет <script> раньше времени — прячем < > & и ' за \u-кодами, как tojson в Jinja!
+fn script_safe_json(json: &str) -> String {
+    json.replace('<', "\\u003c").replace('>', "\\u003e").replace('&', "\\u0026").replace('\'', "\\u0027")
+}
+
+// Число с разделителями — 1 234 567.89 для звёздной бухгалтерии!
+fn format_number(number: f64, decimals: usize, thousands: &str, point: &str) -> String {
+    let formatted = format!("{:.*}", decimals, number.abs());
+    let (int_part, frac_part) = formatted.split_once('.').unwrap_or((&formatted, ""));
+    let mut result = String::new();
+    if number < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        result.push('-');
+    }
+    for (i, digit) in int_part.chars().enumerate() {
+        if i > 0 && (int_part.len() - i) % 3 == 0 {
+            result.push_str(thousands);
+        }
+        result.push(digit);
+    }
+    if !frac_part.is_empty() {
+        result.push_str(point);
+        result.push_str(frac_part);
+    }
+    result
+}
+
+// Дата и время — звёздный календарь без лишних зависимостей!
+struct DateTime {
+    year: i64,   // Год!
+    month: u32,  // Месяц 1..=12!
+    day: u32,    // День 1..=31!
+    hour: u32,   // Часы!
+    minute: u32, // Минуты!
+    second: u32, // Секунды!
+}
+
+impl DateTime {
+    // Форматируем по strftime-образцу: %Y %y %m %d %e %H %M %S %%
+    fn format(&self, format: &str) -> String {
+        let mut result = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                result.push(c);
+                continue;
+            }
+            let _ = match chars.next() {
+                Some('Y') => write!(result, "{:04}", self.year),
+                Some('y') => write!(result, "{:02}", self.year.rem_euclid(100)),
+                Some('m') => write!(result, "{:02}", self.month),
+                Some('d') => write!(result, "{:02}", self.day),
+                Some('e') => write!(result, "{}", self.day),
+                Some('H') => write!(result, "{:02}", self.hour),
+                Some('M') => write!(result, "{:02}", self.minute),
+                Some('S') => write!(result, "{:02}", self.second),
+                Some('%') => write!(result, "%"),
+                Some(other) => write!(result, "%{}", other), // Незнакомый спецификатор — оставляем как есть!
+                None => write!(result, "%"),
+            };
+        }
+        result
+    }
+}
+
+// Момент времени по strftime-образцу — для вывода Value::DateTime!
+pub(crate) fn format_datetime(time: SystemTime, format: &str) -> String {
+    from_unix(unix_seconds(time)).format(format)
+}
+
+// Дата из значения — момент времени, unix-время или ISO-строка вида 2024-03-05[ 14:30[:00]]!
+fn parse_datetime(value: &Value) -> Option<DateTime> {
+    match value {
+        Value::DateTime(time) => Some(from_unix(unix_seconds(*time))),
+        Value::Int(seconds) => Some(from_unix(*seconds)),
+        Value::Float(seconds) => Some(from_unix(*seconds as i64)),
+        Value::String(s) | Value::Safe(s) => {
+            let s = s.trim();
+            if let Ok(seconds) = s.parse::<i64>() {
+                return Some(from_unix(seconds));
+            }
+            let (date, time) = s.split_once(['T', ' ']).unwrap_or((s, ""));
+            let mut date_parts = date.splitn(3, '-');
+            let year = date_parts.next()?.parse().ok()?;
+            let month = date_parts.next()?.parse().ok()?;
+            let day = date_parts.next()?.parse().ok()?;
+            let time = time.trim_end_matches('Z');
+            let time = time.split(['+', '.']).next().unwrap_or("");
+            let mut time_parts = time.split(':').map(|p| p.parse::<u32>());
+            let hour = time_parts.next().unwrap_or(Ok(0)).ok()?;
+            let minute = time_parts.next().unwrap_or(Ok(0)).ok()?;
+            let second = time_parts.next().unwrap_or(Ok(0)).ok()?;
+            let valid = (1..=12).contains(&month) && (1..=31).contains(&day) && hour < 24 && minute < 60 && second < 61;
+            valid.then_some(DateTime { year, month, day, hour, minute, second })
+        }
+        _ => None,
+    }
+}
+
+// Unix-время в календарную дату (UTC) — алгоритм Говарда Хиннанта!
+fn from_unix(seconds: i64) -> DateTime {
+    let days = seconds.div_euclid(86_400);
+    let secs = seconds.rem_euclid(86_400) as u32;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    DateTime { year, month, day, hour: secs / 3_600, minute: secs / 60 % 60, second: secs % 60 }
+}
@@ -1,9 +1,33 @@
 use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::str::FromStr;
+use std::sync::Arc;
+use serde::Serialize; // Любые структуры команды — в рендер без ручной перекладки!
 use thiserror::Error; // Новый помощник для ошибок — звёздный шторм под контролем!
-use serde_json; // Для JSON — машинные орбиты!
-use regex; // Для парсинга шаблонов — звёздный сканер!
-use base64; // Для Protobuf — гиперскорость в байтах!
+use tokio::io::{AsyncWrite, AsyncWriteExt}; // Асинхронные писатели — сокеты tokio!
+
+mod template; // Дерево шаблона — звёздная карта!
+mod engine; // Движок с кешем скомпилированных шаблонов — ангар для карт!
+mod loader; // Загрузчики шаблонов — диск, память или бинарник!
+mod eval; // Вычислитель дерева — штурман карты!
+mod escape; // Экранирование — щит от XSS!
+mod expr; // Выражения в {{ }} — звёздные формулы!
+mod filters; // Фильтры — бортовой арсенал преобразований!
+mod value; // Значения шаблона — звёздная материя!
+mod proto; // Protobuf — настоящий проводной формат!
+mod stream; // Потоковая запись форматов — строка за строкой!
+mod csv; // CSV по RFC 4180 — с диалектами для Excel и TSV!
+
+use escape::Escape;
+use eval::Evaluator;
+use stream::{RowWriter, Source};
+use template::Whitespace;
+pub use csv::{CsvDialect, QuoteStyle};
+pub use engine::{EngineMode, TemplateEngine};
+pub use filters::FilterFn;
+pub use loader::{EmbeddedLoader, FileSystemLoader, MemoryLoader, TemplateLoader, TemplateStamp};
+pub use proto::{FieldKind, MessageDescriptor, PROTO_SCHEMA};
+pub use value::Value;
 
 // Ошибки рендера — штормы в космосе!
 #[derive(Debug, Error)]
@@ -14,6 +38,60 @@ pub enum RenderError {
     FileError(#[from] std::io::Error), // Не нашли файл в космосе — теперь от std::io::Error!
     #[error("Телепорт сломался! Ошибка сериализации: {0}")]
     SerializationError(String), // Ошибка при упаковке данных!
+    #[error("Шаблон '{0}' не найден ни в одном архиве!")]
+    TemplateNotFound(String), // Загрузчик не знает такого шаблона!
+    #[error("Путь '{0}' ведёт за пределы архива шаблонов — туда не летаем!")]
+    ForbiddenTemplatePath(String), // Абсолютный путь, ../ или симлинк наружу!
+    #[error("Шаблоны включают друг друга по кругу: {}", .chain.join(" → "))]
+    IncludeCycle {
+        chain: Vec<String>, // Вся цепочка включений — от корня до повтора!
+    }, // Модуль включает сам себя через соседей!
+    #[error("Слишком глубокая стыковка модулей — больше {max_depth} уровней: {}", .chain.join(" → "))]
+    IncludeTooDeep {
+        max_depth: usize,   // Сколько уровней разрешено!
+        chain: Vec<String>, // Цепочка включений, на которой упёрлись в предел!
+    }, // Включения зашли слишком глубоко!
+    #[error("Слишком глубокая рекурсия макросов — больше {max_depth} вызовов, упёрлись в '{name}'")]
+    MacroTooDeep {
+        max_depth: usize, // Сколько вложенных вызовов разрешено!
+        name: String,     // Макрос, на котором упёрлись в предел!
+    }, // Макрос зовёт сам себя без конца!
+    #[error("Фильтр заклинило! {0}")]
+    FilterError(String), // Фильтр не справился с грузом!
+    #[error("Сбой на маршруте шаблона! {0}")]
+    TemplateError(String), // Шаблон собрался, но полёт по нему не удался!
+    #[error("Карта порвана! Ошибка в шаблоне '{path}' (строка {line}, колонка {column}) на теге `{tag}`: {hint}")]
+    TemplateSyntax {
+        path: String,  // Какой шаблон сломан!
+        line: usize,   // Строка с ошибкой — считаем с единицы!
+        column: usize, // Колонка с ошибкой — тоже с единицы!
+        tag: String,   // Тег, на котором споткнулись!
+        hint: String,  // Подсказка, как починить!
+    }, // Шаблон не парсится — чиним карту до вылета!
+    #[error("Переменная '{name}' не найдена в шаблоне '{path}' (строка {line}, колонка {column})!")]
+    UndefinedVariable {
+        name: String,  // Какую переменную искали!
+        path: String,  // В каком шаблоне!
+        line: usize,   // Строка тега — с единицы!
+        column: usize, // Колонка тега — с единицы!
+    }, // Строгий режим: опечатка в имени — шторм, а не пустота!
+}
+
+// Порядок колонок в CSV, XML, тексте и Markdown — одинаковый от запуска к запуску!
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ColumnOrder {
+    #[default]
+    Sorted,                // По алфавиту — вложенные поля раскладываются в колонки ship.name!
+    Explicit(Vec<String>), // Только эти колонки и в этом порядке — как fields([...]) в запросе yuaidb!
+}
+
+// Что делать с переменной, которой нет в данных!
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UndefinedBehavior {
+    #[default]
+    Lenient, // Пустая строка — как раньше!
+    Debug,   // Видимая метка {{ missing: name }} — сразу видно, что потерялось!
+    Strict,  // Ошибка UndefinedVariable с шаблоном, строкой и колонкой!
 }
 
 // Форматы рендера — выбираем курс!
@@ -23,11 +101,38 @@ pub enum RenderFormat {
     Json,       // JSON — для машинных орбит!
     Xml,        // XML — для ретро-шаттлов!
     Csv,        // CSV — для звёздных таблиц!
+    Tsv,        // TSV — те же таблицы через табуляцию!
     PlainText,  // Текст — просто и чисто!
     Markdown,   // Markdown с шаблонами — для галактических заметок!
     Protobuf,   // Protobuf — гиперскорость в байтах!
 }
 
+impl RenderFormat {
+    // MIME-тип формата — для Content-Type и имён файлов!
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            RenderFormat::Html => "text/html",
+            RenderFormat::Json => "application/json",
+            RenderFormat::Xml => "application/xml",
+            RenderFormat::Csv => "text/csv",
+            RenderFormat::Tsv => "text/tab-separated-values",
+            RenderFormat::PlainText => "text/plain",
+            RenderFormat::Markdown => "text/markdown",
+            RenderFormat::Protobuf => "application/x-protobuf",
+        }
+    }
+
+    // Бинарный ли формат — такие летят в RenderOutput::Bytes, а не строкой!
+    pub fn is_binary(&self) -> bool {
+        matches!(self, RenderFormat::Protobuf)
+    }
+
+    // Кодировка текста — у бинарных форматов её нет!
+    pub fn charset(&self) -> Option<&'static str> {
+        if self.is_binary() { None } else { Some("utf-8") }
+    }
+}
+
 impl FromStr for RenderFormat {
     type Err = RenderError;
 
@@ -38,6 +143,7 @@ impl FromStr for RenderFormat {
             "json" => Ok(RenderFormat::Json),
             "xml" => Ok(RenderFormat::Xml),
             "csv" => Ok(RenderFormat::Csv),
+            "tsv" | "tab" => Ok(RenderFormat::Tsv),
             "text" | "plain" => Ok(RenderFormat::PlainText),
             "markdown" | "md" => Ok(RenderFormat::Markdown),
             "protobuf" | "proto" => Ok(RenderFormat::Protobuf),
@@ -46,251 +152,336 @@ impl FromStr for RenderFormat {
     }
 }
 
-// Токены шаблона — куски звёздной карты!
-#[derive(Debug)]
-enum TemplateToken {
-    Text(String),             // Обычный текст — звёздная пыль!
-    Variable(String),         // Переменная {{ name }} — данные из космоса!
-    ForStart(String, String), // Начало цикла {% for item in items %} — звёздный цикл!
-    ForEnd,                   // Конец цикла {% endfor %} — закрываем орбиту!
-    IfStart(String),          // Начало условия {% if active %} — проверяем звёзды!
-    Else,                     // Альтернатива {% else %} — другой путь!
-    IfEnd,                    // Конец условия {% endif %} — возвращаемся на курс!
-    Include(String),          // Включение {% include "header.html" %} — звёздный модуль!
-}
-
-// Элементы стека — для циклов и условий!
-#[derive(Debug)]
-enum StackItem {
-    ForLoop(String, String, usize), // Для {% for %} — (item_name, list_name, start_pos)
-    IfCond(String, usize),          // Для {% if %} — (condition, start_pos)
-}
-
 // Главный рендер — наш звездолёт!
 pub struct YuaiRender {
     format: RenderFormat,       // Какой формат выбрали?
     template: Option<String>,   // Путь к основному шаблону (если есть)!
+    autoescape: bool,           // Экранировать ли {{ }} — щит поднят по умолчанию!
+    trim_blocks: bool,          // Съедать ли перевод строки после {% %}!
+    lstrip_blocks: bool,        // Съедать ли отступ перед {% %} в начале строки!
+    max_include_depth: usize,   // Сколько уровней {% include %} можно вложить друг в друга!
+    max_macro_depth: usize,     // Сколько вызовов макросов можно вложить друг в друга!
+    undefined: UndefinedBehavior, // Как встречаем неизвестные переменные!
+    columns: ColumnOrder,       // Порядок колонок табличных форматов!
+    csv: CsvDialect,            // Разделитель, кавычки и концы строк CSV!
+    trusted: HashSet<String>,   // Доверенные поля — их значения не экранируем!
+    filters: HashMap<String, Arc<FilterFn>>, // Фильтры команды — поверх встроенных!
+    protobuf_message: Option<MessageDescriptor>, // Своё сообщение для Protobuf — иначе yuairender.Row!
+    protobuf_base64: bool,      // Отдавать Protobuf текстом в base64, а не сырыми байтами!
+    engine: Arc<TemplateEngine>, // Ангар скомпилированных шаблонов — можно делить между рендерами!
 }
 
 impl YuaiRender {
     // Новый рендер — готовим звездолёт к полёту!
     pub fn new(format: &str, template: Option<&str>) -> Result<Self, RenderError> {
+        YuaiRender::with_engine(format, template, Arc::new(TemplateEngine::new())) // Свой ангар — шаблоны кешируются между рендерами!
+    }
+
+    // Новый рендер на общем движке — шаблоны компилируются один раз на всё приложение!
+    pub fn with_engine(format: &str, template: Option<&str>, engine: Arc<TemplateEngine>) -> Result<Self, RenderError> {
         let render_format = RenderFormat::from_str(format)?; // Парсим формат или шторм!
         let template_path = template.map(|t| t.to_string()); // Путь к шаблону — если есть!
         Ok(YuaiRender {
             format: render_format,
             template: template_path,
+            autoescape: true,
+            trim_blocks: false,
+            lstrip_blocks: false,
+            max_include_depth: 32,
+            max_macro_depth: 64,
+            undefined: UndefinedBehavior::default(),
+            columns: ColumnOrder::default(),
+            csv: CsvDialect::default(),
+            trusted: HashSet::new(),
+            filters: HashMap::new(),
+            protobuf_message: None,
+            protobuf_base64: false,
+            engine,
         })
     }
 
-    // Рендерим данные — запускаем двигатели!
+    // Включаем или выключаем автоэкранирование — без щита летают только смельчаки!
+    pub fn set_autoescape(&mut self, enabled: bool) {
+        self.autoescape = enabled;
+    }
+
+    // Съедаем перевод строки после {% %} — циклы не оставляют пустых строк в Markdown и CSV!
+    pub fn set_trim_blocks(&mut self, enabled: bool) {
+        self.trim_blocks = enabled;
+    }
+
+    // Съедаем пробелы и табы перед {% %}, если тег стоит в начале строки — отступы в шаблоне не попадают в вывод!
+    pub fn set_lstrip_blocks(&mut self, enabled: bool) {
+        self.lstrip_blocks = enabled;
+    }
+
+    // Предел вложенности {% include %} — глубже летят только циклы и ошибки!
+    pub fn set_max_include_depth(&mut self, depth: usize) {
+        self.max_include_depth = depth;
+    }
+
+    // Предел вложенности вызовов макросов — бесконечная рекурсия не уронит процесс!
+    pub fn set_max_macro_depth(&mut self, depth: usize) {
+        self.max_macro_depth = depth;
+    }
+
+    // Как встречаем неизвестные переменные — молча, меткой или ошибкой!
+    pub fn set_undefined(&mut self, behavior: UndefinedBehavior) {
+        self.undefined = behavior;
+    }
+
+    // Порядок колонок для CSV, XML, текста и Markdown!
+    pub fn set_column_order(&mut self, order: ColumnOrder) {
+        self.columns = order;
+    }
+
+    // Колонки по списку — передайте тот же список, что и в query.fields(...)!
+    pub fn set_columns(&mut self, columns: &[&str]) {
+        self.columns = ColumnOrder::Explicit(columns.iter().map(|column| column.to_string()).collect());
+    }
+
+    // Диалект CSV — CsvDialect::excel() для Excel, свой разделитель или кавычки!
+    pub fn set_csv_dialect(&mut self, dialect: CsvDialect) {
+        self.csv = dialect;
+    }
+
+    // Помечаем поле как доверенное — его значения вставляются без экранирования!
+    pub fn trust_field(&mut self, field: &str) {
+        self.trusted.insert(field.to_string());
+    }
+
+    // Регистрируем свой фильтр — {{ price | currency("₽") }} без форка крейта!
+    pub fn register_filter<F>(&mut self, name: &str, filter: F)
+    where
+        F: Fn(&Value, &[Value]) -> Result<Value, RenderError> + Send + Sync + 'static,
+    {
+        self.filters.insert(name.to_string(), Arc::new(filter)); // Одноимённый встроенный фильтр перекрываем!
+    }
+
+    // Кодируем строки в своё сообщение вместо универсального yuairender.Row!
+    pub fn set_protobuf_message(&mut self, descriptor: MessageDescriptor) {
+        self.protobuf_message = Some(descriptor);
+    }
+
+    // Protobuf текстом в base64 — для каналов, где байты не пролетают!
+    pub fn set_protobuf_base64(&mut self, enabled: bool) {
+        self.protobuf_base64 = enabled;
+    }
+
+    // Формат, в который рендерим!
+    pub fn format(&self) -> &RenderFormat {
+        &self.format
+    }
+
+    // Готовый Content-Type: text/html; charset=utf-8 или application/x-protobuf! Protobuf в base64 — это уже текст.
+    pub fn content_type(&self) -> String {
+        if self.format.is_binary() && self.protobuf_base64 {
+            return "text/plain; charset=utf-8".to_string();
+        }
+        match self.format.charset() {
+            Some(charset) => format!("{}; charset={}", self.format.mime_type(), charset),
+            None => self.format.mime_type().to_string(),
+        }
+    }
+
+    // Движок этого рендера — отдайте его другим рендерам через with_engine!
+    pub fn engine(&self) -> &Arc<TemplateEngine> {
+        &self.engine
+    }
+
+    // Прогрев — компилируем шаблон со всеми include, extends и import до первого запроса!
+    pub fn warm_up(&self) -> Result<(), RenderError> {
+        let Some(default_path) = self.default_template() else {
+            return Ok(()); // Без шаблона и греть нечего!
+        };
+        self.engine.warm_up(self.template_path(default_path), self.whitespace())
+    }
+
+    // Рендерим данные — запускаем двигатели! Строки из yuaidb приводятся к типам без потерь.
     pub fn render(&self, data: Option<Vec<HashMap<String, String>>>) -> Result<RenderOutput, RenderError> {
-        match self.format {
-            RenderFormat::Html => {
-                let template_content = self.load_template("templates/default.html")?; // Загружаем карту!
-                let rendered = self.render_template(&template_content, data.unwrap_or_default(), &mut HashSet::new())?;
-                Ok(RenderOutput::Rendered(rendered))
-            }
-            RenderFormat::Json => {
-                let json = serde_json::to_string(&data)
-                    .map_err(|e| RenderError::SerializationError(format!("Не могу закодировать в JSON: {}", e)))?;
-                Ok(RenderOutput::Rendered(json))
-            }
-            RenderFormat::Xml => {
-                let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rows>");
-                if let Some(rows) = &data {
-                    if rows.is_empty() { return Ok(RenderOutput::Rendered("<rows></rows>".to_string())); }
-                    for row in rows {
-                        output.push_str("\n  <row>");
-                        for (key, value) in row {
-                            output.push_str(&format!("\n    <{}>{}</{}>", key, value, key));
-                        }
-                        output.push_str("\n  </row>");
-                    }
-                    output.push_str("\n</rows>");
-                } else {
-                    output.push_str("<rows></rows>");
-                }
-                Ok(RenderOutput::Rendered(output))
-            }
-            RenderFormat::Csv => {
-                let mut output = String::new();
-                if let Some(rows) = &data {
-                    if rows.is_empty() { return Ok(RenderOutput::Rendered("".to_string())); }
-                    let first_row = &rows[0];
-                    output.push_str(&first_row.keys().map(|k| k.as_str()).collect::<Vec<_>>().join(","));
-                    output.push('\n');
-                    for row in rows {
-                        let values: Vec<String> = row.values().map(|v| format!("\"{}\"", v.replace("\"", "\"\""))).collect();
-                        output.push_str(&values.join(","));
-                        output.push('\n');
-                    }
-                }
-                Ok(RenderOutput::Rendered(output))
-            }
-            RenderFormat::PlainText => {
-                let mut output = String::new();
-                if let Some(rows) = &data {
-                    if rows.is_empty() { return Ok(RenderOutput::Rendered("Эй! Космос пуст!".to_string())); }
-                    for row in rows {
-                        for (key, value) in row {
-                            output.push_str(&format!("{}: {}\n", key, value));
-                        }
-                        output.push_str("---\n");
-                    }
-                } else {
-                    output.push_str("Эй! Космос пуст!");
-                }
-                Ok(RenderOutput::Rendered(output))
-            }
-            RenderFormat::Markdown => {
-                let template_content = self.load_template("templates/default.md")?; // Загружаем карту!
-                let rendered = self.render_template(&template_content, data.unwrap_or_default(), &mut HashSet::new())?;
-                Ok(RenderOutput::Rendered(rendered))
-            }
-            RenderFormat::Protobuf => {
-                let bytes = bincode::serialize(&data)
-                    .map_err(|e| RenderError::SerializationError(format!("Не могу закодировать в байты: {}", e)))?;
-                let encoded = base64::encode(bytes);
-                Ok(RenderOutput::Rendered(encoded))
-            }
+        self.render_value(data)
+    }
+
+    // Рендерим любую структуру с Serialize — шаблоны видят вложенные поля, JSON и XML сохраняют вложенность!
+    pub fn render_serialize<T: Serialize + ?Sized>(&self, data: &T) -> Result<RenderOutput, RenderError> {
+        let data = serde_json::to_value(data)
+            .map_err(|e| RenderError::SerializationError(format!("Не могу разобрать данные: {}", e)))?;
+        self.render_value(data)
+    }
+
+    // Рендерим типизированные данные — список строк-словарей, один словарь или что угодно из Value!
+    pub fn render_value(&self, data: impl Into<Value>) -> Result<RenderOutput, RenderError> {
+        let data = data.into();
+        if let Some(default_path) = self.default_template() {
+            let rendered = self.render_template(self.template_path(default_path), data)?;
+            return Ok(RenderOutput::Rendered(rendered));
+        }
+        let mut output = Vec::new();
+        self.render_to(data, &mut output)?;
+        if self.format.is_binary() && !self.protobuf_base64 {
+            return Ok(RenderOutput::Bytes(output)); // Бинарный груз — без перекодировок!
         }
+        let text = String::from_utf8(output).map_err(|e| RenderError::SerializationError(format!("Вывод не в UTF-8: {}", e)))?;
+        Ok(RenderOutput::Rendered(text))
     }
 
-    // Загружаем шаблон — берём карту из звёздного архива!
-    fn load_template(&self, default_path: &str) -> Result<String, RenderError> {
-        let path = self.template.as_ref().map(|s| s.as_str()).unwrap_or(default_path);
-        std::fs::read_to_string(path)?; // Читаем или шторм!
-        Ok(std::fs::read_to_string(path)?) // Возвращаем содержимое!
+    // Рендерим прямо в писатель — файл, сокет или stdout! Оберните его в BufWriter для скорости.
+    pub fn render_to<W: Write>(&self, data: impl Into<Value>, mut writer: W) -> Result<(), RenderError> {
+        let data = data.into();
+        if let Some(json) = self.json_document(&data)? {
+            writer.write_all(json.as_bytes())?;
+            return Ok(writer.flush()?);
+        }
+        match into_rows(data) {
+            Some(rows) => self.write_rows(rows.into_iter(), Source::InMemory, writer),
+            None => self.write_rows(std::iter::empty(), Source::Absent, writer),
+        }
     }
 
-    // Рендерим шаблон — превращаем карту в звёздный путь с защитой от зацикливания!
-    fn render_template(&self, template: &str, data: Vec<HashMap<String, String>>, included: &mut HashSet<String>) -> Result<String, RenderError> {
-        let tokens = self.parse_template(template); // Парсим карту!
-        let mut output = String::new();
-        let mut stack = Vec::new(); // Стек для циклов и условий!
-
-        for token in tokens {
-            match token {
-                TemplateToken::Text(text) => output.push_str(&text), // Просто текст — в космос!
-                TemplateToken::Variable(var) => {
-                    if let Some(row) = data.first() {
-                        if let Some(value) = row.get(&var) {
-                            output.push_str(value); // Нашли данные — добавляем!
-                        }
-                    }
-                }
-                TemplateToken::ForStart(item_name, list_name) => {
-                    stack.push(StackItem::ForLoop(item_name, list_name, output.len())); // Запоминаем начало цикла!
-                }
-                TemplateToken::ForEnd => {
-                    if let Some(StackItem::ForLoop(item_name, list_name, start_pos)) = stack.pop() {
-                        let loop_content = output[start_pos..].to_string(); // Копируем в String
-                        output.truncate(start_pos);
-                        for row in &data {
-                            if let Some(list) = row.get(&list_name) {
-                                for item in list.split(',') {
-                                    let mut temp = loop_content.clone();
-                                    temp = temp.replace(&format!("{{ {} }}", item_name), item);
-                                    output.push_str(&temp); // Повторяем для каждого элемента!
-                                }
-                            }
-                        }
-                    }
-                }
-                TemplateToken::IfStart(condition) => {
-                    stack.push(StackItem::IfCond(condition, output.len())); // Запоминаем начало условия!
-                }
-                TemplateToken::Else => {
-                    if let Some(StackItem::IfCond(condition, start_pos)) = stack.last_mut() {
-                        let if_content = output[*start_pos..].to_string(); // Копируем в String
-                        output.truncate(*start_pos);
-                        if let Some(row) = data.first() {
-                            if row.get(condition).map(|v| v == "true").unwrap_or(false) {
-                                output.push_str(&if_content); // Условие истинно!
-                            }
-                        }
-                        *start_pos = output.len(); // Перемещаем для else!
-                    }
-                }
-                TemplateToken::IfEnd => {
-                    if let Some(StackItem::IfCond(condition, start_pos)) = stack.pop() {
-                        let else_content = output[start_pos..].to_string(); // Копируем в String
-                        output.truncate(start_pos);
-                        if let Some(row) = data.first() {
-                            if !row.get(&condition).map(|v| v == "true").unwrap_or(false) {
-                                output.push_str(&else_content); // Условие ложно — берём else!
-                            }
-                        }
-                    }
-                }
-                TemplateToken::Include(file) => {
-                    // Проверяем, не включали ли этот файл раньше!
-                    if included.contains(&file) {
-                        println!("Осторожно! '{}' уже включён — пропускаем, чтобы не зациклиться!", file);
-                        continue; // Пропускаем, чтобы избежать бесконечной рекурсии!
-                    }
-                    included.insert(file.clone()); // Добавляем в список включённых!
-                    let include_content = std::fs::read_to_string(&file)?; // Читаем модуль или шторм!
-                    let rendered_include = self.render_template(&include_content, data.clone(), included)?;
-                    output.push_str(&rendered_include);
-                }
-            }
+    // Рендерим строки по одной из итератора — миллион строк из yuaidb без миллиона строк в памяти!
+    // Шаблонам нужны все строки сразу (rows можно обойти дважды), поэтому для HTML и Markdown они собираются.
+    pub fn render_iter_to<I, W>(&self, rows: I, writer: W) -> Result<(), RenderError>
+    where
+        I: IntoIterator,
+        I::Item: Into<Value>,
+        W: Write,
+    {
+        self.write_rows(rows.into_iter().map(Into::into), Source::Streamed, writer)
+    }
+
+    // Асинхронный render_to — для сокетов и файлов tokio!
+    pub async fn render_to_async<W: AsyncWrite + Unpin>(&self, data: impl Into<Value>, mut writer: W) -> Result<(), RenderError> {
+        let data = data.into();
+        if let Some(json) = self.json_document(&data)? {
+            writer.write_all(json.as_bytes()).await?;
+            return Ok(writer.flush().await?);
+        }
+        match into_rows(data) {
+            Some(rows) => self.write_rows_async(rows.into_iter(), Source::InMemory, writer).await,
+            None => self.write_rows_async(std::iter::empty(), Source::Absent, writer).await,
         }
-        Ok(output) // Карта готова — полный вперёд!
     }
 
-    // Парсим шаблон — разбиваем карту на звёздные куски!
-    fn parse_template(&self, template: &str) -> Vec<TemplateToken> {
-        let mut tokens = Vec::new();
-        let mut remaining = template;
-        let re = regex::Regex::new(r"(\{\{.*?\}\}|\{%.*?%\})").unwrap(); // Сканер для токенов!
-
-        while let Some(mat) = re.find(remaining) {
-            let before = &remaining[..mat.start()];
-            if !before.is_empty() {
-                tokens.push(TemplateToken::Text(before.to_string()));
-            }
-
-            let token_str = mat.as_str();
-            if token_str.starts_with("{{") && token_str.ends_with("}}") {
-                let var = token_str[2..token_str.len() - 2].trim().to_string();
-                tokens.push(TemplateToken::Variable(var));
-            } else if token_str.starts_with("{%") && token_str.ends_with("%}") {
-                let content = token_str[2..token_str.len() - 2].trim();
-                if content.starts_with("for ") {
-                    let parts: Vec<&str> = content[4..].split(" in ").collect();
-                    if parts.len() == 2 {
-                        let item_name = parts[0].trim().to_string();
-                        let list_name = parts[1].trim().to_string();
-                        tokens.push(TemplateToken::ForStart(item_name, list_name));
-                    }
-                } else if content == "endfor" {
-                    tokens.push(TemplateToken::ForEnd);
-                } else if content.starts_with("if ") {
-                    let condition = content[3..].trim().to_string();
-                    tokens.push(TemplateToken::IfStart(condition));
-                } else if content == "else" {
-                    tokens.push(TemplateToken::Else);
-                } else if content == "endif" {
-                    tokens.push(TemplateToken::IfEnd);
-                } else if content.starts_with("include ") {
-                    let file = content[8..].trim().to_string();
-                    tokens.push(TemplateToken::Include(file));
-                }
-            }
-
-            remaining = &remaining[mat.end()..];
+    // Асинхронный render_iter_to — каждая строка улетает в писатель, пока готовится следующая!
+    pub async fn render_iter_to_async<I, W>(&self, rows: I, writer: W) -> Result<(), RenderError>
+    where
+        I: IntoIterator,
+        I::Item: Into<Value>,
+        W: AsyncWrite + Unpin,
+    {
+        self.write_rows_async(rows.into_iter().map(Into::into), Source::Streamed, writer).await
+    }
+
+    // Пишем строки в писатель — шаблон целиком, табличные форматы построчно!
+    fn write_rows(&self, rows: impl Iterator<Item = Value>, source: Source, mut writer: impl Write) -> Result<(), RenderError> {
+        if let Some(default_path) = self.default_template() {
+            let rendered = self.render_template(self.template_path(default_path), Value::List(rows.collect()))?;
+            writer.write_all(rendered.as_bytes())?;
+            return Ok(writer.flush()?);
         }
-        if !remaining.is_empty() {
-            tokens.push(TemplateToken::Text(remaining.to_string()));
+        let mut stream = RowWriter::new(self, source);
+        for row in stream.prepare(rows) {
+            writer.write_all(&stream.row(row)?)?;
         }
-        tokens // Куски карты готовы — в путь!
+        writer.write_all(&stream.finish())?;
+        Ok(writer.flush()?)
+    }
+
+    // То же самое для асинхронного писателя!
+    async fn write_rows_async(&self, rows: impl Iterator<Item = Value>, source: Source, mut writer: impl AsyncWrite + Unpin) -> Result<(), RenderError> {
+        if let Some(default_path) = self.default_template() {
+            let rendered = self.render_template(self.template_path(default_path), Value::List(rows.collect()))?;
+            writer.write_all(rendered.as_bytes()).await?;
+            return Ok(writer.flush().await?);
+        }
+        let mut stream = RowWriter::new(self, source);
+        for row in stream.prepare(rows) {
+            let chunk = stream.row(row)?;
+            writer.write_all(&chunk).await?;
+        }
+        writer.write_all(&stream.finish()).await?;
+        Ok(writer.flush().await?)
+    }
+
+    // JSON не из списка пишем как есть — объект остаётся объектом, а не массивом из одного! Построчно идут только списки.
+    fn json_document(&self, data: &Value) -> Result<Option<String>, RenderError> {
+        if self.format != RenderFormat::Json || matches!(data, Value::List(_)) {
+            return Ok(None);
+        }
+        serde_json::to_string(&data.to_json())
+            .map(Some)
+            .map_err(|e| RenderError::SerializationError(format!("Не могу закодировать в JSON: {}", e)))
+    }
+
+    // Щит под формат — HTML и Markdown экранируем по-своему!
+    fn escape(&self) -> Escape {
+        match self.format {
+            RenderFormat::Html => Escape::Html,
+            RenderFormat::Markdown => Escape::Markdown,
+            _ => Escape::None,
+        }
+    }
+
+    // Настройки пробелов для парсера!
+    fn whitespace(&self) -> Whitespace {
+        Whitespace { trim_blocks: self.trim_blocks, lstrip_blocks: self.lstrip_blocks }
+    }
+
+    // Шаблон по умолчанию — только у HTML и Markdown, остальные форматы пишет RowWriter!
+    fn default_template(&self) -> Option<&'static str> {
+        match self.format {
+            RenderFormat::Html => Some("default.html"), // Имена от корня загрузчика — как у всех шаблонов!
+            RenderFormat::Markdown => Some("default.md"),
+            _ => None,
+        }
+    }
+
+    // Путь к шаблону — свой или по умолчанию!
+    fn template_path<'a>(&'a self, default_path: &'a str) -> &'a str {
+        self.template.as_deref().unwrap_or(default_path)
+    }
+
+    // Рендерим шаблон — превращаем карту в звёздный путь с защитой от зацикливания!
+    fn render_template(&self, path: &str, data: Value) -> Result<String, RenderError> {
+        let nodes = self.engine.load_root(path, self.whitespace())?; // Дерево из ангара — парсим только при первом полёте!
+        let mut output = String::new();
+        let mut evaluator = Evaluator::new(self, path, into_rows(data).unwrap_or_default()); // Строки данных — в rows!
+        evaluator.render_document(path, nodes, &mut output)?; // Проходим дерево узел за узлом, с наследованием!
+        Ok(output) // Карта готова — полный вперёд!
     }
 }
 
 // Результат рендера — звёздный груз!
 pub enum RenderOutput {
     Rendered(String),                  // Готовая строка — миссия выполнена!
-    Raw(Option<Vec<HashMap<String, String>>>), // Сырые данные — для смелых пилотов!
+    Raw(Value),                        // Сырые данные — для смелых пилотов!
+    Bytes(Vec<u8>),                    // Бинарный груз — Protobuf прямо в сокет или файл!
+}
+
+impl RenderOutput {
+    // Байты для сокета или файла — текст в UTF-8, сырые данные в JSON!
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            RenderOutput::Rendered(text) => text.into_bytes(),
+            RenderOutput::Raw(data) => data.to_json().to_string().into_bytes(),
+            RenderOutput::Bytes(bytes) => bytes,
+        }
+    }
+
+    // Текст, если груз текстовый — бинарный в строку не превращаем!
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            RenderOutput::Rendered(text) => Some(text),
+            RenderOutput::Raw(_) | RenderOutput::Bytes(_) => None,
+        }
+    }
+}
+
+// Строки данных: Null — данных нет, список — строки, одиночное значение — одна строка!
+fn into_rows(data: Value) -> Option<Vec<Value>> {
+    match data {
+        Value::Null => None,
+        Value::List(rows) => Some(rows),
+        row => Some(vec![row]),
+    }
 }
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use crate::RenderError;
+
+// Отметка шаблона — время изменения и размер: правку внутри одной секунды выдаст размер!
+pub type TemplateStamp = (Option<SystemTime>, u64);
+
+// Загрузчик шаблонов — откуда движок берёт исходники по имени из {% include %} и компании!
+pub trait TemplateLoader: Send + Sync {
+    // Исходник шаблона по имени — или TemplateNotFound, если такого нет!
+    fn load(&self, name: &str) -> Result<String, RenderError>;
+
+    // Отметка шаблона — для горячей перезагрузки. None — шаблон не меняется никогда!
+    fn modified(&self, name: &str) -> Result<Option<TemplateStamp>, RenderError> {
+        let _ = name;
+        Ok(None)
+    }
+}
+
+// Загрузчик с диска — ищем шаблон по очереди в корне и путях поиска!
+pub struct FileSystemLoader {
+    roots: Vec<PathBuf>, // Корень и пути поиска — в порядке приоритета!
+}
+
+impl FileSystemLoader {
+    // Загрузчик с корнем — имена шаблонов считаются от него, а не от рабочей папки процесса!
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileSystemLoader { roots: vec![root.into()] }
+    }
+
+    // Ещё одна папка для поиска — заглядываем в неё, если в предыдущих шаблона нет!
+    pub fn add_search_path(&mut self, path: impl Into<PathBuf>) {
+        self.roots.push(path.into());
+    }
+
+    // Первый существующий файл с таким именем — и только внутри своего корня, симлинки наружу не пускаем!
+    fn resolve(&self, name: &str) -> Result<PathBuf, RenderError> {
+        check_name(name)?;
+        for root in &self.roots {
+            let path = root.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            let real = path.canonicalize().map_err(|e| not_found_or(name, e))?;
+            if !real.starts_with(root.canonicalize()?) {
+                return Err(RenderError::ForbiddenTemplatePath(name.to_string())); // Симлинк смотрит за пределы архива!
+            }
+            return Ok(real);
+        }
+        Err(RenderError::TemplateNotFound(name.to_string()))
+    }
+}
+
+impl TemplateLoader for FileSystemLoader {
+    fn load(&self, name: &str) -> Result<String, RenderError> {
+        let path = self.resolve(name)?;
+        std::fs::read_to_string(&path).map_err(|e| not_found_or(name, e)) // Читаем модуль или шторм!
+    }
+
+    fn modified(&self, name: &str) -> Result<Option<TemplateStamp>, RenderError> {
+        let path = self.resolve(name)?;
+        let metadata = std::fs::metadata(&path).map_err(|e| not_found_or(name, e))?;
+        Ok(Some((metadata.modified().ok(), metadata.len())))
+    }
+}
+
+// Загрузчик корневого шаблона приложения — путь как есть, от рабочей папки! Имена из шаблонов сюда не попадают.
+pub(crate) struct PathLoader;
+
+impl TemplateLoader for PathLoader {
+    fn load(&self, name: &str) -> Result<String, RenderError> {
+        std::fs::read_to_string(name).map_err(|e| not_found_or(name, e))
+    }
+
+    fn modified(&self, name: &str) -> Result<Option<TemplateStamp>, RenderError> {
+        let metadata = std::fs::metadata(name).map_err(|e| not_found_or(name, e))?;
+        Ok(Some((metadata.modified().ok(), metadata.len())))
+    }
+}
+
+// Имя шаблона — только относительный путь вниз по архиву: без /etc/passwd, C:\ и ../!
+pub(crate) fn check_name(name: &str) -> Result<(), RenderError> {
+    let escapes = name.is_empty()
+        || name.starts_with(['/', '\\'])
+        || name.split(['/', '\\']).any(|part| part == "..")
+        || Path::new(name).components().any(|c| matches!(c, Component::RootDir | Component::Prefix(_) | Component::ParentDir));
+    if escapes { Err(RenderError::ForbiddenTemplatePath(name.to_string())) } else { Ok(()) }
+}
+
+// Файл пропал между поиском и чтением — это тоже «не найден»!
+fn not_found_or(name: &str, e: std::io::Error) -> RenderError {
+    if e.kind() == ErrorKind::NotFound { RenderError::TemplateNotFound(name.to_string()) } else { e.into() }
+}
+
+// Загрузчик из памяти — шаблоны прямо в коде, для тестов и генерации на лету!
+#[derive(Default)]
+pub struct MemoryLoader {
+    templates: HashMap<String, String>, // Имя → исходник!
+}
+
+impl MemoryLoader {
+    // Пустой склад шаблонов!
+    pub fn new() -> Self {
+        MemoryLoader::default()
+    }
+
+    // Кладём шаблон на склад — одноимённый заменяем!
+    pub fn insert(&mut self, name: &str, source: &str) {
+        self.templates.insert(name.to_string(), source.to_string());
+    }
+}
+
+impl From<HashMap<String, String>> for MemoryLoader {
+    fn from(templates: HashMap<String, String>) -> Self {
+        MemoryLoader { templates }
+    }
+}
+
+impl TemplateLoader for MemoryLoader {
+    fn load(&self, name: &str) -> Result<String, RenderError> {
+        self.templates.get(name).cloned().ok_or_else(|| RenderError::TemplateNotFound(name.to_string()))
+    }
+}
+
+// Встроенный загрузчик — шаблоны вшиты в бинарник на этапе компиляции!
+// Собирается макросом embed_templates!("templates" => "base.html", ...).
+pub struct EmbeddedLoader {
+    templates: &'static [(&'static str, &'static str)], // Имя → исходник, зашитые в бинарник!
+}
+
+impl EmbeddedLoader {
+    // Загрузчик из готовой таблицы — обычно её строит embed_templates!
+    pub const fn new(templates: &'static [(&'static str, &'static str)]) -> Self {
+        EmbeddedLoader { templates }
+    }
+}
+
+impl TemplateLoader for EmbeddedLoader {
+    fn load(&self, name: &str) -> Result<String, RenderError> {
+        self.templates
+            .iter()
+            .find(|(embedded, _)| *embedded == name)
+            .map(|(_, source)| source.to_string())
+            .ok_or_else(|| RenderError::TemplateNotFound(name.to_string()))
+    }
+}
+
+// Вшиваем шаблоны в бинарник: пути считаются от корня крейта и становятся именами шаблонов!
+// С корнем — embed_templates!("templates" => "base.html", ...) — имена считаются от него, как у FileSystemLoader.
+#[macro_export]
+macro_rules! embed_templates {
+    ($root:literal => $($path:literal),* $(,)?) => {
+        $crate::EmbeddedLoader::new(&[
+            $(($path, include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $root, "/", $path)))),*
+        ])
+    };
+    ($($path:literal),* $(,)?) => {
+        $crate::EmbeddedLoader::new(&[
+            $(($path, include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $path)))),*
+        ])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Свой архив во временной папке — шаблон внутри и секрет снаружи!
+    fn archive(name: &str) -> (PathBuf, FileSystemLoader) {
+        let base = std::env::temp_dir().join(format!("yuairender-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("root/partials")).unwrap();
+        std::fs::write(base.join("root/partials/card.html"), "карта").unwrap();
+        std::fs::write(base.join("secret.txt"), "сокровище").unwrap();
+        let loader = FileSystemLoader::new(base.join("root"));
+        (base, loader)
+    }
+
+    fn forbidden(result: Result<String, RenderError>) -> bool {
+        matches!(result, Err(RenderError::ForbiddenTemplatePath(_)))
+    }
+
+    #[test]
+    fn loads_relative_names_inside_root() {
+        let (base, loader) = archive("inside");
+        assert_eq!(loader.load("partials/card.html").unwrap(), "карта");
+        assert!(matches!(loader.load("partials/missing.html"), Err(RenderError::TemplateNotFound(_))));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn rejects_parent_dir() {
+        let (base, loader) = archive("parent");
+        assert!(forbidden(loader.load("../secret.txt")));
+        assert!(forbidden(loader.load("partials/../../secret.txt")));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        let (base, loader) = archive("absolute");
+        assert!(forbidden(loader.load(base.join("secret.txt").to_str().unwrap())));
+        assert!(forbidden(loader.load("/etc/passwd")));
+        assert!(forbidden(loader.load("")));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn rejects_backslash_paths() {
+        let (base, loader) = archive("backslash");
+        assert!(forbidden(loader.load("..\\secret.txt")));
+        assert!(forbidden(loader.load("partials\\..\\..\\secret.txt")));
+        assert!(forbidden(loader.load("\\etc\\passwd")));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlink_outside_root() {
+        let (base, loader) = archive("symlink");
+        std::os::unix::fs::symlink(base.join("secret.txt"), base.join("root/partials/secret.html")).unwrap();
+        std::os::unix::fs::symlink(base.join("root/partials/card.html"), base.join("root/alias.html")).unwrap();
+        assert!(forbidden(loader.load("partials/secret.html")));
+        assert!(matches!(loader.modified("partials/secret.html"), Err(RenderError::ForbiddenTemplatePath(_))));
+        assert_eq!(loader.load("alias.html").unwrap(), "карта"); // Симлинк внутри архива — можно!
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -71,11 +71,11 @@ async fn main() {
         None
     };
 
-    // Рисуем карту сокровищ в HTML!
-    let html = match YuaiRender::new("html", Some("templates/pirates.html")) {
-        Ok(html_renderer) => match html_renderer.render(data.clone()) {
+    // Рисуем карту сокровищ в HTML — оболочка страницы в base.html из папки templates!
+    let response = match YuaiRender::new("html", Some("pirates.html")) {
+        Ok(html_renderer) => match html_renderer.render(data) {
             Ok(RenderOutput::Rendered(html)) => html, // Карта готова, капитан!
-            Ok(RenderOutput::Raw(_)) => unreachable!(), // Секретный код, сюда не попадём!
+            Ok(RenderOutput::Raw(_) | RenderOutput::Bytes(_)) => unreachable!(), // Секретный код, сюда не попадём!
             Err(e) => {
                 println!("Шторм в рендере HTML: {}. Кидаем заглушку!", e);
                 "<p>Ошибка: звёзды скрыты!</p>".to_string()
@@ -87,42 +87,6 @@ async fn main() {
         }
     };
 
-    // Пакуем добычу в JSON для космических шпионов!
-    let json = match YuaiRender::new("json", None) {
-        Ok(json_renderer) => match json_renderer.render(data) {
-            Ok(RenderOutput::Rendered(json)) => json, // Данные в сундуке JSON!
-            Ok(RenderOutput::Raw(_)) => unreachable!(), // Тайный ход, не для нас!
-            Err(e) => {
-                println!("Ошибка в упаковке JSON: {}. Пустой сундук!", e);
-                "{}".to_string()
-            }
-        },
-        Err(e) => {
-            println!("JSON-машина сломалась: {}. Берём пустышку!", e);
-            "{}".to_string()
-        }
-    };
-
-    // Собираем звёздный корабль HTML с добычей!
-    let response = format!(
-        r#"
-        <!DOCTYPE html>
-        <html>
-        <head>
-            <title>Звёздный пират</title>
-        </head>
-        <body>
-            <div id="app">{}</div>
-            <script type="text/javascript">
-                window.__INITIAL_DATA__ = {};
-            </script>
-            <script src="/hydrate.js"></script>
-        </body>
-        </html>
-        "#,
-        html, json
-    );
-
     // Показываем карту галактической добычи!
     println!("Готовый HTML с гидрацией:\n{}", response);
 }
@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::value::{unix_seconds, Value};
+use crate::RenderError;
+
+// Схема универсальных строк — отдайте её protoc и читайте вывод как yuairender.Rows!
+pub const PROTO_SCHEMA: &str = include_str!("../proto/yuairender.proto");
+
+// Типы проводного формата — как байты лежат на гиперканале!
+const VARINT: u8 = 0;  // Целые переменной длины!
+const FIXED64: u8 = 1; // 8 байт — double!
+const LEN: u8 = 2;     // Длина и содержимое — строки, байты, вложенные сообщения!
+const FIXED32: u8 = 5; // 4 байта — float!
+
+// Тип поля сообщения — как в .proto!
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldKind {
+    Double,                     // double!
+    Float,                      // float!
+    Int32,                      // int32!
+    Int64,                      // int64!
+    Uint32,                     // uint32!
+    Uint64,                     // uint64!
+    Sint32,                     // sint32 — отрицательные числа без лишних байтов!
+    Sint64,                     // sint64!
+    Bool,                       // bool!
+    String,                     // string!
+    Bytes,                      // bytes!
+    Message(MessageDescriptor), // Вложенное сообщение — поле-словарь!
+}
+
+// Поле сообщения — имя в данных, номер в схеме и тип!
+#[derive(Debug, Clone, PartialEq)]
+struct FieldDescriptor {
+    name: String,    // Имя поля в строке данных — "p.name" или "ship"!
+    number: u32,     // Номер поля в .proto!
+    kind: FieldKind, // Тип поля!
+    repeated: bool,  // repeated — значение берём списком!
+}
+
+// Описание своего сообщения — строки рендера кодируются в него вместо yuairender.Row!
+// Вывод — это `message <Имя>List { repeated <Имя> rows = 1; }`: по проводу он совпадает с Rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageDescriptor {
+    name: String,                 // Имя сообщения — для ошибок!
+    fields: Vec<FieldDescriptor>, // Поля по порядку номеров в схеме!
+}
+
+impl MessageDescriptor {
+    // Пустое сообщение — поля добавляем через field!
+    pub fn new(name: &str) -> Self {
+        MessageDescriptor { name: name.to_string(), fields: Vec::new() }
+    }
+
+    // Одиночное поле: .field("p.name", 1, FieldKind::String)!
+    pub fn field(mut self, name: &str, number: u32, kind: FieldKind) -> Self {
+        self.fields.push(FieldDescriptor { name: name.to_string(), number, kind, repeated: false });
+        self
+    }
+
+    // repeated-поле — значение в данных должно быть списком!
+    pub fn repeated_field(mut self, name: &str, number: u32, kind: FieldKind) -> Self {
+        self.fields.push(FieldDescriptor { name: name.to_string(), number, kind, repeated: true });
+        self
+    }
+
+    // Кодируем словарь в это сообщение — пустые и отсутствующие поля пропускаем, как proto3!
+    fn encode(&self, fields: &BTreeMap<String, Value>, output: &mut Vec<u8>) -> Result<(), RenderError> {
+        for field in &self.fields {
+            let value = match lookup(fields, &field.name) {
+                None | Some(Value::Null) => continue,
+                Some(value) => value,
+            };
+            match (field.repeated, value) {
+                (true, Value::List(items)) => {
+                    for item in items {
+                        self.encode_field(field, item, output)?;
+                    }
+                }
+                (true, _) => return Err(self.error(field, "repeated-поле ждёт список")),
+                (false, value) => self.encode_field(field, value, output)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Одно значение поля — приводим к типу схемы или шторм!
+    fn encode_field(&self, field: &FieldDescriptor, value: &Value, output: &mut Vec<u8>) -> Result<(), RenderError> {
+        let number = field.number;
+        match &field.kind {
+            FieldKind::Double => {
+                let number_value = value.as_f64().ok_or_else(|| self.error(field, "ждём число"))?;
+                write_key(number, FIXED64, output);
+                output.extend_from_slice(&number_value.to_le_bytes());
+            }
+            FieldKind::Float => {
+                let number_value = value.as_f64().ok_or_else(|| self.error(field, "ждём число"))? as f32;
+                write_key(number, FIXED32, output);
+                output.extend_from_slice(&number_value.to_le_bytes());
+            }
+            FieldKind::Int32 | FieldKind::Int64 => {
+                let int = self.as_int(field, value)?;
+                write_key(number, VARINT, output);
+                write_varint(int as u64, output); // Отрицательные — 10 байт, как велит протокол!
+            }
+            FieldKind::Uint32 | FieldKind::Uint64 => {
+                let int = self.as_int(field, value)?;
+                let uint = u64::try_from(int).map_err(|_| self.error(field, "беззнаковое поле не принимает отрицательные числа"))?;
+                write_key(number, VARINT, output);
+                write_varint(uint, output);
+            }
+            FieldKind::Sint32 | FieldKind::Sint64 => {
+                let int = self.as_int(field, value)?;
+                write_key(number, VARINT, output);
+                write_varint(zigzag(int), output);
+            }
+            FieldKind::Bool => {
+                write_key(number, VARINT, output);
+                write_varint(u64::from(value.is_truthy()), output);
+            }
+            FieldKind::String => write_len(number, value.to_string().as_bytes(), output),
+            FieldKind::Bytes => match value {
+                Value::Bytes(bytes) => write_len(number, bytes, output),
+                other => write_len(number, other.to_string().as_bytes(), output),
+            },
+            FieldKind::Message(descriptor) => {
+                let Value::Map(nested) = value else {
+                    return Err(self.error(field, "вложенное сообщение ждёт словарь"));
+                };
+                let mut message = Vec::new();
+                descriptor.encode(nested, &mut message)?;
+                write_len(number, &message, output);
+            }
+        }
+        Ok(())
+    }
+
+    // Целое из значения — строки из yuaidb тоже годятся! 32-битные поля проверяем на переполнение.
+    fn as_int(&self, field: &FieldDescriptor, value: &Value) -> Result<i64, RenderError> {
+        let int = match value {
+            Value::Int(int) => *int,
+            Value::Bool(b) => i64::from(*b),
+            Value::Float(f) if f.fract() == 0.0 => *f as i64,
+            Value::DateTime(time) => unix_seconds(*time),
+            Value::String(s) | Value::Safe(s) => s.trim().parse().map_err(|_| self.error(field, "ждём целое число"))?,
+            _ => return Err(self.error(field, "ждём целое число")),
+        };
+        let fits = match field.kind {
+            FieldKind::Int32 | FieldKind::Sint32 => i32::try_from(int).is_ok(),
+            FieldKind::Uint32 => u32::try_from(int).is_ok(),
+            _ => true,
+        };
+        if fits { Ok(int) } else { Err(self.error(field, &format!("число {} не влезает в 32 бита", int))) }
+    }
+
+    // Ошибка с именем сообщения и поля — сразу видно, что чинить!
+    fn error(&self, field: &FieldDescriptor, hint: &str) -> RenderError {
+        RenderError::SerializationError(format!("Protobuf: поле '{}' сообщения {}: {}", field.name, self.name, hint))
+    }
+}
+
+// Одна строка как элемент Rows.rows — по своей схеме или универсальной yuairender.Row!
+pub(crate) fn encode_row(row: &Value, descriptor: Option<&MessageDescriptor>, output: &mut Vec<u8>) -> Result<(), RenderError> {
+    let fields = match row {
+        Value::Map(fields) => fields.clone(),
+        other => BTreeMap::from([("value".to_string(), other.clone())]), // Одиночное значение — поле value!
+    };
+    let mut message = Vec::new();
+    match descriptor {
+        Some(descriptor) => descriptor.encode(&fields, &mut message)?,
+        None => encode_fields(&fields, &mut message),
+    }
+    write_len(1, &message, output); // Rows.rows!
+    Ok(())
+}
+
+// Словарь как yuairender.Row — map<string, Value> по проводу это повтор пар key/value!
+fn encode_fields(fields: &BTreeMap<String, Value>, output: &mut Vec<u8>) {
+    for (key, value) in fields {
+        let mut entry = Vec::new();
+        write_len(1, key.as_bytes(), &mut entry);
+        let mut encoded = Vec::new();
+        encode_value(value, &mut encoded);
+        write_len(2, &encoded, &mut entry);
+        write_len(1, &entry, output);
+    }
+}
+
+// Значение как yuairender.Value — номер поля в oneof говорит о типе!
+fn encode_value(value: &Value, output: &mut Vec<u8>) {
+    match value {
+        Value::Null => {
+            write_key(1, VARINT, output);
+            write_varint(0, output);
+        }
+        Value::Bool(b) => {
+            write_key(2, VARINT, output);
+            write_varint(u64::from(*b), output);
+        }
+        Value::Int(int) => {
+            write_key(3, VARINT, output);
+            write_varint(zigzag(*int), output);
+        }
+        Value::Float(float) => {
+            write_key(4, FIXED64, output);
+            output.extend_from_slice(&float.to_le_bytes());
+        }
+        Value::String(s) | Value::Safe(s) => write_len(5, s.as_bytes(), output),
+        Value::List(items) => {
+            let mut list = Vec::new();
+            for item in items {
+                let mut encoded = Vec::new();
+                encode_value(item, &mut encoded);
+                write_len(1, &encoded, &mut list);
+            }
+            write_len(6, &list, output);
+        }
+        Value::Map(fields) => {
+            let mut row = Vec::new();
+            encode_fields(fields, &mut row);
+            write_len(7, &row, output);
+        }
+        Value::Bytes(bytes) => write_len(8, bytes, output),
+        Value::DateTime(time) => {
+            let (seconds, nanos) = timestamp(*time);
+            let mut encoded = Vec::new();
+            if seconds != 0 {
+                write_key(1, VARINT, &mut encoded);
+                write_varint(seconds as u64, &mut encoded);
+            }
+            if nanos != 0 {
+                write_key(2, VARINT, &mut encoded);
+                write_varint(u64::from(nanos), &mut encoded);
+            }
+            write_len(9, &encoded, output);
+        }
+    }
+}
+
+// Поле строки по имени — целиком ("p.name" из yuaidb) или путём во вложенные словари!
+fn lookup<'a>(fields: &'a BTreeMap<String, Value>, name: &str) -> Option<&'a Value> {
+    if let Some(value) = fields.get(name) {
+        return Some(value);
+    }
+    let (head, rest) = name.split_once('.')?;
+    match fields.get(head)? {
+        Value::Map(nested) => lookup(nested, rest),
+        _ => None,
+    }
+}
+
+// Секунды и наносекунды как в google.protobuf.Timestamp — наносекунды всегда неотрицательные!
+fn timestamp(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(before) => {
+            let before = before.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+// Ключ поля — номер и тип проводного формата в одном varint!
+fn write_key(number: u32, wire_type: u8, output: &mut Vec<u8>) {
+    write_varint(u64::from(number) << 3 | u64::from(wire_type), output);
+}
+
+// Поле с длиной — строки, байты и вложенные сообщения!
+fn write_len(number: u32, bytes: &[u8], output: &mut Vec<u8>) {
+    write_key(number, LEN, output);
+    write_varint(bytes.len() as u64, output);
+    output.extend_from_slice(bytes);
+}
+
+// Varint — по 7 бит в байте, старший бит значит «дальше ещё»!
+fn write_varint(mut value: u64, output: &mut Vec<u8>) {
+    while value >= 0x80 {
+        output.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+// ZigZag для sint — маленькие отрицательные числа занимают мало байтов!
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    // Строка из пар ключ-значение!
+    fn row(fields: &[(&str, Value)]) -> Value {
+        Value::Map(fields.iter().map(|(key, value)| (key.to_string(), value.clone())).collect())
+    }
+
+    fn encode(row: &Value, descriptor: Option<&MessageDescriptor>) -> Result<Vec<u8>, RenderError> {
+        let mut output = Vec::new();
+        encode_row(row, descriptor, &mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn universal_row_from_yuaidb_strings() {
+        let row = Value::from(HashMap::from([("a".to_string(), "1".to_string())])); // "1" без потерь становится int!
+        assert_eq!(encode(&row, None).unwrap(), [10, 9, 10, 7, 10, 1, 97, 18, 2, 24, 2]);
+    }
+
+    #[test]
+    fn universal_values() {
+        // Row.fields{"v": Value.null_value}!
+        assert_eq!(encode(&row(&[("v", Value::Null)]), None).unwrap(), [10, 9, 10, 7, 10, 1, 118, 18, 2, 8, 0]);
+        // Value.int_value — zigzag: -1 → 1!
+        assert_eq!(encode(&row(&[("v", Value::Int(-1))]), None).unwrap(), [10, 9, 10, 7, 10, 1, 118, 18, 2, 24, 1]);
+        // Value.string_value!
+        assert_eq!(encode(&row(&[("v", Value::from("hi"))]), None).unwrap(), [10, 11, 10, 9, 10, 1, 118, 18, 4, 42, 2, 104, 105]);
+        // Value.list_value с одним bool!
+        assert_eq!(
+            encode(&row(&[("v", Value::List(vec![Value::Bool(true)]))]), None).unwrap(),
+            [10, 13, 10, 11, 10, 1, 118, 18, 6, 50, 4, 10, 2, 16, 1]
+        );
+        // Одиночное значение — поле value!
+        assert_eq!(encode(&Value::Bool(false), None).unwrap(), [10, 13, 10, 11, 10, 5, 118, 97, 108, 117, 101, 18, 2, 16, 0]);
+    }
+
+    #[test]
+    fn universal_timestamp() {
+        let time = UNIX_EPOCH + Duration::new(1, 500_000_000);
+        let value = [74, 8, 8, 1, 16, 128, 202, 181, 238, 1]; // Value.timestamp_value{seconds: 1, nanos: 500000000}!
+        let mut expected = vec![10, 17, 10, 15, 10, 1, 116, 18, 10];
+        expected.extend_from_slice(&value);
+        assert_eq!(encode(&row(&[("t", Value::DateTime(time))]), None).unwrap(), expected);
+        // До эпохи — секунды вниз, наносекунды вверх: -0.5 с → {seconds: -1, nanos: 500000000}!
+        assert_eq!(timestamp(UNIX_EPOCH - Duration::from_millis(500)), (-1, 500_000_000));
+    }
+
+    #[test]
+    fn negative_int32_takes_ten_bytes() {
+        let descriptor = MessageDescriptor::new("Crew").field("n", 1, FieldKind::Int32);
+        assert_eq!(
+            encode(&row(&[("n", Value::Int(-1))]), Some(&descriptor)).unwrap(),
+            [10, 11, 8, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1]
+        );
+    }
+
+    #[test]
+    fn sint64_uses_zigzag() {
+        let descriptor = MessageDescriptor::new("Crew").field("s", 2, FieldKind::Sint64);
+        assert_eq!(encode(&row(&[("s", Value::Int(-2))]), Some(&descriptor)).unwrap(), [10, 2, 16, 3]);
+        assert_eq!(encode(&row(&[("s", Value::Int(1))]), Some(&descriptor)).unwrap(), [10, 2, 16, 2]);
+        assert_eq!(zigzag(i64::MIN), u64::MAX);
+    }
+
+    #[test]
+    fn nested_message_and_dotted_names() {
+        let ship = MessageDescriptor::new("Ship").field("name", 1, FieldKind::String);
+        let descriptor = MessageDescriptor::new("Crew").field("ship", 3, FieldKind::Message(ship)).field("p.name", 4, FieldKind::String);
+        let data = row(&[("ship", row(&[("name", Value::from("K"))])), ("p.name", Value::from("J"))]);
+        assert_eq!(encode(&data, Some(&descriptor)).unwrap(), [10, 8, 26, 3, 10, 1, 75, 34, 1, 74]);
+        let error = encode(&row(&[("ship", Value::from("K"))]), Some(&descriptor));
+        assert!(matches!(error, Err(RenderError::SerializationError(_)))); // Вложенное сообщение ждёт словарь!
+    }
+
+    #[test]
+    fn repeated_fields() {
+        let descriptor = MessageDescriptor::new("Crew").repeated_field("tags", 4, FieldKind::Int32);
+        let data = row(&[("tags", Value::List(vec![Value::Int(1), Value::Int(2)]))]);
+        assert_eq!(encode(&data, Some(&descriptor)).unwrap(), [10, 4, 32, 1, 32, 2]);
+        assert!(encode(&row(&[("tags", Value::Int(1))]), Some(&descriptor)).is_err()); // repeated ждёт список!
+    }
+
+    #[test]
+    fn skips_missing_fields_and_checks_ranges() {
+        let descriptor = MessageDescriptor::new("Crew").field("n", 1, FieldKind::Int32).field("u", 2, FieldKind::Uint32);
+        assert_eq!(encode(&row(&[("other", Value::Int(7))]), Some(&descriptor)).unwrap(), [10, 0]); // Как proto3 — пустое не пишем!
+        assert!(encode(&row(&[("n", Value::Int(i64::from(i32::MAX) + 1))]), Some(&descriptor)).is_err());
+        assert!(encode(&row(&[("u", Value::Int(-1))]), Some(&descriptor)).is_err());
+        assert_eq!(encode(&row(&[("u", Value::from("300"))]), Some(&descriptor)).unwrap(), [10, 3, 16, 172, 2]); // Строка из yuaidb — тоже число!
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::csv::CsvDialect;
+use crate::escape::Escape;
+use crate::eval::resolve_path;
+use crate::proto;
+use crate::value::Value;
+use crate::{ColumnOrder, RenderError, RenderFormat, YuaiRender};
+
+// Откуда пришли строки — от этого зависит, можно ли заглянуть во все сразу!
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Source {
+    Absent,   // Данных нет совсем — None в JSON это null, а не []!
+    InMemory, // Весь список уже в памяти — шапка CSV из ключей всех строк бесплатна!
+    Streamed, // Итератор — шапку берём у первой строки, если диалект не просит union_header!
+}
+
+// Потоковый писатель табличных форматов — каждая строка данных сразу превращается в байты!
+// Память не растёт с числом строк: держим только счётчик, шапку CSV и хвост base64.
+// Исключение — CsvDialect::union_header в потоке: шапка из всех ключей требует дочитать строки до конца.
+pub(crate) struct RowWriter<'a> {
+    render: &'a YuaiRender,      // Формат и настройки Protobuf!
+    source: Source,              // Откуда строки — нет их, список или итератор!
+    rows: usize,                 // Сколько строк уже записали!
+    pending: Vec<u8>,            // Байты Protobuf, не влезшие в тройку base64!
+    dialect: CsvDialect,         // Диалект CSV — у TSV разделитель всегда табуляция!
+    header: Option<Vec<String>>, // Колонки CSV — все ключи строк или колонки первой строки!
+}
+
+impl<'a> RowWriter<'a> {
+    // Новый писатель — Source::Absent, если данных нет совсем!
+    pub(crate) fn new(render: &'a YuaiRender, source: Source) -> Self {
+        let mut dialect = render.csv.clone();
+        if render.format == RenderFormat::Tsv {
+            dialect.delimiter = '\t';
+        }
+        RowWriter { render, source, rows: 0, pending: Vec::new(), dialect, header: None }
+    }
+
+    // Готовим строки к записи — CSV без явных колонок сначала собирает шапку из ключей всех строк!
+    // Поток буферизуем только по просьбе union_header — иначе шапку задаёт первая строка, а лишнее поле потом — ошибка.
+    pub(crate) fn prepare(&mut self, rows: impl Iterator<Item = Value>) -> impl Iterator<Item = Value> {
+        let mut rows = rows;
+        let mut buffered = Vec::new();
+        let union = self.source == Source::InMemory || self.dialect.union_header;
+        if self.is_csv() && self.render.columns == ColumnOrder::Sorted && union {
+            buffered = rows.by_ref().collect();
+            let keys: BTreeSet<String> = buffered.iter().flat_map(|row| row_fields(row).into_keys()).collect();
+            self.header = Some(keys.into_iter().collect()); // Нет поля в строке — пустая ячейка!
+        }
+        buffered.into_iter().chain(rows)
+    }
+
+    // Одна строка данных — и шапка документа перед первой!
+    pub(crate) fn row(&mut self, row: Value) -> Result<Vec<u8>, RenderError> {
+        let first = self.rows == 0;
+        self.rows += 1;
+        let mut output = String::new();
+        match self.render.format {
+            RenderFormat::Json => {
+                output.push(if first { '[' } else { ',' });
+                let json = serde_json::to_string(&row.to_json())
+                    .map_err(|e| RenderError::SerializationError(format!("Не могу закодировать в JSON: {}", e)))?;
+                output.push_str(&json);
+            }
+            RenderFormat::Xml => {
+                if first {
+                    output.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rows>");
+                }
+                match &self.render.columns {
+                    ColumnOrder::Sorted => write_xml("row", &row, 1, &mut output)?, // Вложенность как есть!
+                    ColumnOrder::Explicit(columns) => {
+                        let cells = columns.iter().map(|column| (column.clone(), cell(&row, column))).collect();
+                        write_xml("row", &Value::Map(cells), 1, &mut output)?;
+                    }
+                }
+            }
+            RenderFormat::Csv | RenderFormat::Tsv => {
+                if self.header.is_none() {
+                    self.header = Some(self.columns(&row));
+                }
+                let header = self.header.as_deref().unwrap_or_default();
+                if self.render.columns == ColumnOrder::Sorted {
+                    // Шапка из первой строки, а у этой поле сверх неё — шторм, а не тихо потерянная колонка!
+                    if let Some(extra) = row_fields(&row).into_keys().find(|key| header.binary_search(key).is_err()) {
+                        return Err(RenderError::SerializationError(format!(
+                            "CSV: в строке {} поле '{}', которого нет в шапке — задайте колонки через set_columns или включите union_header",
+                            self.rows, extra
+                        )));
+                    }
+                }
+                if first {
+                    self.csv_preamble(header, &mut output);
+                }
+                // Значения строго по шапке — колонки не разъедутся, даже если у строк разные поля!
+                let cells: Vec<Value> = header.iter().map(|column| cell(&row, column)).collect();
+                self.dialect.write_record(&cells, &mut output);
+            }
+            RenderFormat::PlainText => {
+                for column in self.columns(&row) {
+                    output.push_str(&format!("{}: {}\n", column, cell(&row, &column)));
+                }
+                output.push_str("---\n");
+            }
+            RenderFormat::Protobuf => {
+                let mut bytes = Vec::new();
+                proto::encode_row(&row, self.render.protobuf_message.as_ref(), &mut bytes)?;
+                return Ok(self.protobuf(bytes));
+            }
+            RenderFormat::Html | RenderFormat::Markdown => {} // Шаблоны рендерит Evaluator, а не писатель!
+        }
+        Ok(output.into_bytes())
+    }
+
+    // Хвост документа — или заглушка, если строк не было!
+    pub(crate) fn finish(self) -> Vec<u8> {
+        let output = match (self.render.format.clone(), self.rows) {
+            (RenderFormat::Json, 0) if self.source == Source::Absent => "null",
+            (RenderFormat::Json, 0) => "[]",
+            (RenderFormat::Json, _) => "]",
+            (RenderFormat::Xml, 0) => "<rows></rows>",
+            (RenderFormat::Xml, _) => "\n</rows>",
+            (RenderFormat::PlainText, 0) => "Эй! Космос пуст!",
+            (RenderFormat::Protobuf, _) if self.render.protobuf_base64 => return base64::encode(&self.pending).into_bytes(),
+            (RenderFormat::Csv | RenderFormat::Tsv, 0) => {
+                // Строк нет, но колонки известны — отдаём хотя бы шапку!
+                let mut output = String::new();
+                if let ColumnOrder::Explicit(columns) = &self.render.columns {
+                    self.csv_preamble(columns, &mut output);
+                }
+                return output.into_bytes();
+            }
+            _ => "",
+        };
+        output.as_bytes().to_vec()
+    }
+
+    // Начало CSV — BOM для Excel и шапка, если их просили!
+    fn csv_preamble(&self, header: &[String], output: &mut String) {
+        if self.dialect.bom {
+            output.push('\u{feff}');
+        }
+        if self.dialect.header {
+            let names: Vec<Value> = header.iter().map(|column| Value::from(column.as_str())).collect();
+            self.dialect.write_record(&names, output);
+        }
+    }
+
+    // CSV или TSV!
+    fn is_csv(&self) -> bool {
+        matches!(self.render.format, RenderFormat::Csv | RenderFormat::Tsv)
+    }
+
+    // Колонки строки — по списку или по алфавиту её полей!
+    fn columns(&self, row: &Value) -> Vec<String> {
+        match &self.render.columns {
+            ColumnOrder::Explicit(columns) => columns.clone(),
+            ColumnOrder::Sorted => row_fields(row).into_keys().collect(),
+        }
+    }
+
+    // Protobuf сырыми байтами — или base64 кусками по три байта, хвост ждёт следующей строки!
+    fn protobuf(&mut self, bytes: Vec<u8>) -> Vec<u8> {
+        if !self.render.protobuf_base64 {
+            return bytes;
+        }
+        self.pending.extend_from_slice(&bytes);
+        let ready = self.pending.len() / 3 * 3;
+        let chunk: Vec<u8> = self.pending.drain(..ready).collect();
+        base64::encode(chunk).into_bytes()
+    }
+}
+
+// Поля строки для таблиц — вложенные словари раскладываем в колонки s.name, одиночное значение ложится в value!
+pub(crate) fn row_fields(row: &Value) -> BTreeMap<String, Value> {
+    let mut fields = BTreeMap::new();
+    match row {
+        Value::Map(_) => flatten("", row, &mut fields),
+        other => {
+            fields.insert("value".to_string(), other.clone());
+        }
+    }
+    fields
+}
+
+// Ячейка колонки — ключ целиком ("p.name") или путь во вложенные поля (ship.name); нет поля — пусто!
+fn cell(row: &Value, column: &str) -> Value {
+    match row {
+        Value::Map(_) => resolve_path(row, column).unwrap_or(Value::Null),
+        other if column == "value" => other.clone(), // Одиночное значение живёт в колонке value!
+        _ => Value::Null,
+    }
+}
+
+// Разворачиваем вложенные словари в плоские ключи через точку — как p.name из yuaidb!
+fn flatten(prefix: &str, value: &Value, fields: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Map(nested) if !nested.is_empty() => {
+            for (key, value) in nested {
+                let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&key, value, fields);
+            }
+        }
+        other => {
+            fields.insert(prefix.to_string(), other.clone());
+        }
+    }
+}
+
+// Элемент XML со вложенностью — словари становятся дочерними тегами, списки — повторами <item>!
+// Ключ, который не годится в имя тега, — шторм: "bad key<" не сломает документ и не впрыснет разметку.
+fn write_xml(tag: &str, value: &Value, depth: usize, output: &mut String) -> Result<(), RenderError> {
+    if !is_xml_name(tag) {
+        return Err(RenderError::SerializationError(format!("XML: ключ '{}' не годится в имя тега", tag)));
+    }
+    let indent = "  ".repeat(depth);
+    output.push_str(&format!("\n{}<{}>", indent, tag));
+    match value {
+        Value::Map(fields) if !fields.is_empty() => {
+            for (key, value) in fields {
+                write_xml(key, value, depth + 1, output)?;
+            }
+            output.push_str(&format!("\n{}", indent));
+        }
+        Value::List(items) if !items.is_empty() => {
+            for item in items {
+                write_xml("item", item, depth + 1, output)?;
+            }
+            output.push_str(&format!("\n{}", indent));
+        }
+        Value::Map(_) | Value::List(_) => {} // Пустой трюм — пустой тег!
+        scalar => Escape::Html.apply(&scalar.to_string(), output), // Текст с < и & не ломает документ!
+    }
+    output.push_str(&format!("</{}>", tag));
+    Ok(())
+}
+
+// Имя тега по продукции NCName из XML 1.0 — Name без двоеточия, чтобы не выдумывать пространства имён!
+// p.name и кириллица годятся, "1x", "bad key<" и пустая строка — нет.
+fn is_xml_name(name: &str) -> bool {
+    let start = |c: char| {
+        matches!(c, 'A'..='Z' | '_' | 'a'..='z' | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' | '\u{F8}'..='\u{2FF}'
+            | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}' | '\u{200C}'..='\u{200D}' | '\u{2070}'..='\u{218F}'
+            | '\u{2C00}'..='\u{2FEF}' | '\u{3001}'..='\u{D7FF}' | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}'
+            | '\u{10000}'..='\u{EFFFF}')
+    };
+    let rest = |c: char| start(c) || matches!(c, '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}');
+    let mut chars = name.chars();
+    chars.next().is_some_and(start) && chars.all(rest)
+}
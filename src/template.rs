@@ -0,0 +1,659 @@
+use std::collections::HashSet;
+use std::sync::{Arc, OnceLock};
+use regex::Regex; // Для парсинга шаблонов — звёздный сканер!
+use crate::expr::{parse_assignments, parse_expr, parse_include, Expr, IncludeSpec, Kwargs, Location};
+use crate::RenderError;
+
+// Токены шаблона — куски звёздной карты!
+#[derive(Debug)]
+enum TemplateToken {
+    Text(String),                   // Обычный текст — звёздная пыль!
+    Variable(Expr),                 // Переменная {{ name | upper }} — данные из космоса!
+    ForStart(String, Expr),         // Начало цикла {% for item in items %} — звёздный цикл!
+    ForEnd,                         // Конец цикла {% endfor %} — закрываем орбиту!
+    IfStart(Expr),                  // Начало условия {% if active %} — проверяем звёзды!
+    ElseIf(Expr),                   // Ещё одно условие {% elif other %} — запасной маршрут!
+    Else,                           // Альтернатива {% else %} — другой путь!
+    IfEnd,                          // Конец условия {% endif %} — возвращаемся на курс!
+    Include(IncludeSpec),           // Включение {% include "header.html" with {...} only %} — звёздный модуль!
+    AutoEscapeStart(bool),          // Начало {% autoescape false %} — опускаем или поднимаем щит!
+    AutoEscapeEnd,                  // Конец {% endautoescape %} — щит как был!
+    Extends(String),                // Наследование {% extends "base.html" %} — строимся на чужом корпусе!
+    BlockStart(String),             // Начало {% block content %} — отсек, который можно переопределить!
+    BlockEnd(Option<String>),       // Конец {% endblock %} или {% endblock content %}!
+    MacroStart(String, Vec<Param>), // Начало {% macro card(title, size=2) %} — звёздная деталь на все случаи!
+    MacroEnd,                       // Конец {% endmacro %}!
+    Import(String, String),         // Импорт {% import "components.html" as ui %} — макросы из другого шаблона!
+    Set(Kwargs),                    // Присваивание {% set speed = s.speed %} — в текущую область видимости!
+    SetBlockStart(String),          // Начало {% set card %} — захватываем отрендеренный кусок в переменную!
+    SetEnd,                         // Конец {% endset %}!
+    WithStart(Kwargs),              // Начало {% with speed = s.speed %} — своя область видимости!
+    WithEnd,                        // Конец {% endwith %} — переменные блока растворяются в космосе!
+}
+
+// Параметр макроса — имя и значение по умолчанию!
+pub(crate) type Param = (String, Option<Expr>);
+
+// Макрос — звёздная деталь, которую можно вызвать сколько угодно раз!
+#[derive(Debug)]
+pub(crate) struct Macro {
+    pub(crate) name: String,       // Имя макроса!
+    pub(crate) params: Vec<Param>, // Параметры — по порядку, с умолчаниями!
+    pub(crate) body: Vec<Node>,    // Тело — рендерим на каждый вызов!
+}
+
+// Сканеры тегов — компилируем один раз на весь полёт!
+static OPEN_RE: OnceLock<Regex> = OnceLock::new();
+static ENDRAW_RE: OnceLock<Regex> = OnceLock::new();
+
+// Токен с координатами — где на карте он найден!
+#[derive(Debug)]
+struct Spanned {
+    token: TemplateToken, // Сам токен!
+    offset: usize,        // Байтовое смещение в шаблоне!
+    tag: String,          // Исходный текст тега — для сообщений об ошибках!
+}
+
+// Управление пробелами вокруг тегов — настройки звездолёта!
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Whitespace {
+    pub(crate) trim_blocks: bool,   // Съедаем перевод строки сразу после {% %}!
+    pub(crate) lstrip_blocks: bool, // Съедаем отступ перед {% %}, если тег стоит в начале строки!
+}
+
+// Что срезать с начала следующего текста — решает предыдущий тег!
+#[derive(Clone, Copy, PartialEq)]
+enum TrimNext {
+    Nothing,      // Текст как есть!
+    Whitespace,   // После -%} — все пробелы и переводы строк!
+    FirstNewline, // После {% %} с trim_blocks — один перевод строки!
+}
+
+// Все теги, которые понимает картограф — для подсказок!
+const KNOWN_TAGS: &[&str] = &[
+    "for", "endfor", "if", "elif", "else", "endif", "include", "autoescape", "endautoescape", "extends", "block", "endblock",
+    "macro", "endmacro", "import", "set", "endset", "with", "endwith", "raw", "endraw",
+];
+
+// Узлы дерева шаблона — звёздные системы, вложенные друг в друга!
+#[derive(Debug)]
+pub(crate) enum Node {
+    Text(String),     // Обычный текст — летит как есть!
+    Variable(Expr), // Выражение {{ name | upper }} — вычисляем и выводим!
+    For {
+        item: String,         // Имя элемента цикла!
+        list: Expr,           // Откуда берём элементы!
+        body: Vec<Node>,      // Тело цикла — рендерим на каждом витке!
+        else_body: Vec<Node>, // Ветка {% else %} — если лететь не по чему!
+    },
+    If {
+        branches: Vec<(Expr, Vec<Node>)>, // Ветки if/elif — условие и тело!
+        else_body: Vec<Node>,             // Ветка else — если все звёзды погасли!
+    },
+    Include(IncludeSpec), // Включение другого шаблона — стыковка модулей!
+    AutoEscape {
+        enabled: bool,   // Экранировать ли внутри блока!
+        body: Vec<Node>, // Содержимое блока!
+    },
+    Extends(String), // Родительский шаблон — рендерим его с нашими блоками!
+    Block {
+        name: String,         // Имя отсека!
+        body: Arc<Vec<Node>>, // Содержимое по умолчанию — делим с наследниками без копирования!
+    },
+    Macro(Arc<Macro>), // Объявление макроса — вызывать можно из любого места шаблона!
+    Import {
+        path: String,  // Откуда берём макросы!
+        alias: String, // Под каким именем они видны: ui.card!
+    },
+    Set(Kwargs), // Присваивания — живут до конца текущей области видимости!
+    SetBlock {
+        name: String,    // Куда кладём захваченный кусок!
+        body: Vec<Node>, // Что рендерим в переменную!
+    },
+    With {
+        assignments: Kwargs, // Переменные блока!
+        body: Vec<Node>,     // Где они видны!
+    },
+}
+
+// Парсим шаблон — строим дерево звёздной карты или сообщаем, где сбились с курса!
+pub(crate) fn parse_template(path: &str, template: &str, whitespace: Whitespace) -> Result<Vec<Node>, RenderError> {
+    let mut parser = Parser {
+        path,
+        source: template,
+        whitespace,
+        tokens: Vec::new().into_iter(),
+        depth: 0,
+        extends: false,
+        blocks: HashSet::new(),
+    };
+    parser.tokens = parser.tokenize()?.into_iter();
+    let (nodes, end) = parser.parse_block()?;
+    if let Some(end) = end {
+        return Err(parser.error(&end, "закрывающий тег без открывающего — лишний шлюз!"));
+    }
+    Ok(nodes) // Дерево готово — в путь!
+}
+
+// Парсер — картограф, который помнит, какую карту читает!
+struct Parser<'a> {
+    path: &'a str,                       // Путь к шаблону — для ошибок!
+    source: &'a str,                     // Исходный текст шаблона!
+    whitespace: Whitespace,              // Как обращаться с пробелами вокруг тегов!
+    tokens: std::vec::IntoIter<Spanned>, // Токены, которые ещё не разобраны!
+    depth: usize,                        // Глубина вложенности блоков!
+    extends: bool,                       // Уже видели {% extends %}!
+    blocks: HashSet<String>,             // Имена блоков — дубликаты запрещены!
+}
+
+impl Parser<'_> {
+    // Разбиваем карту на звёздные куски — с {%- -%}, комментариями и {% raw %}!
+    fn tokenize(&self) -> Result<Vec<Spanned>, RenderError> {
+        let mut tokens = Vec::new();
+        let mut last = 0;
+        let mut trim_next = TrimNext::Nothing;
+        // Сканер начала тегов — конец ищет tag_end, теги могут занимать несколько строк!
+        let opener = OPEN_RE.get_or_init(|| Regex::new(r"\{[{%#]").unwrap());
+        let endraw = ENDRAW_RE.get_or_init(|| Regex::new(r"\{%[-+]?\s*endraw\s*-?%\}").unwrap()); // Конец сырого блока!
+
+        while let Some(open) = opener.find_at(self.source, last) {
+            let offset = open.start();
+            let end = self.tag_end(offset)?;
+            let tag = &self.source[offset..end];
+            let is_block = !tag.starts_with("{{"); // Теги и комментарии — для trim_blocks и lstrip_blocks!
+            self.push_text(&mut tokens, last, offset, trim_next, tag, is_block);
+            last = end;
+            trim_next = self.trim_after(tag, is_block);
+
+            let token = match &tag[..2] {
+                "{#" => continue, // Комментарий — в рендер не летит!
+                "{{" => self.parse_variable(offset, tag)?,
+                _ if tag_body(tag).trim() == "raw" => {
+                    // Сырой блок — всё до {% endraw %} летит текстом, как есть!
+                    let Some(end) = endraw.find_at(self.source, last) else {
+                        return Err(self.error_at(offset, tag, "сырой блок не закрыт — нужен {% endraw %}!"));
+                    };
+                    let raw = trim_text(&self.source[last..end.start()], trim_next, end.as_str().starts_with("{%-"));
+                    if !raw.is_empty() {
+                        tokens.push(Spanned { token: TemplateToken::Text(raw.to_string()), offset: last, tag: String::new() });
+                    }
+                    last = end.end();
+                    trim_next = self.trim_after(end.as_str(), true);
+                    continue;
+                }
+                _ => self.parse_tag(offset, tag)?,
+            };
+            let token = self.locate(offset, token);
+            tokens.push(Spanned { token, offset, tag: tag.to_string() });
+        }
+        self.push_text(&mut tokens, last, self.source.len(), trim_next, "", false);
+        Ok(tokens) // Куски карты готовы!
+    }
+
+    // Конец тега, открытого в start — кавычки в {{ }} и {% %} не обрывают тег, даже если внутри '}}'!
+    // Нет закрывающей скобки до конца карты или до следующего тега — шторм, а не полстраницы текстом.
+    fn tag_end(&self, start: usize) -> Result<usize, RenderError> {
+        let opener = &self.source[start..start + 2];
+        let closer = match opener {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let body = start + 2;
+        let mut quote = None;
+        let mut stop = self.source[start..].find('\n').map_or(self.source.len(), |line| start + line); // Докуда показать в ошибке!
+        let mut chars = self.source[body..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            let rest = &self.source[body + i..];
+            match (quote, c) {
+                (Some(_), '\\') => {
+                    chars.next(); // Экранированный символ строки — \' не закрывает её!
+                }
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                _ if rest.starts_with(closer) => return Ok(body + i + closer.len()),
+                (None, '"' | '\'') if opener != "{#" => quote = Some(c),
+                _ if opener != "{#" && (rest.starts_with("{{") || rest.starts_with("{%")) => {
+                    stop = stop.min(body + i); // Следующий тег раньше конца!
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let tag = self.source[start..stop].trim_end();
+        Err(self.error_at(start, tag, &format!("тег не закрыт — нужен {}!", closer)))
+    }
+
+    // Текст между тегами — срезаем пробелы по маркерам и настройкам!
+    fn push_text(&self, tokens: &mut Vec<Spanned>, start: usize, end: usize, trim_next: TrimNext, tag: &str, is_block: bool) {
+        let mut text = trim_text(&self.source[start..end], trim_next, tag.get(2..3) == Some("-"));
+        if is_block && self.whitespace.lstrip_blocks && tag.get(2..3) != Some("+") {
+            // Отступ перед тегом в начале строки — долой, {%+ %} его сохраняет!
+            let indented = text.trim_end_matches([' ', '\t']);
+            let indent_start = end - (text.len() - indented.len());
+            if indent_start == 0 || self.source[..indent_start].ends_with('\n') {
+                text = indented;
+            }
+        }
+        if !text.is_empty() {
+            tokens.push(Spanned { token: TemplateToken::Text(text.to_string()), offset: start, tag: String::new() });
+        }
+    }
+
+    // Что срежет тег с начала следующего текста!
+    fn trim_after(&self, tag: &str, is_block: bool) -> TrimNext {
+        if tag[..tag.len() - 2].ends_with('-') {
+            TrimNext::Whitespace
+        } else if is_block && self.whitespace.trim_blocks {
+            TrimNext::FirstNewline
+        } else {
+            TrimNext::Nothing
+        }
+    }
+
+    // Разбираем {{ name | upper | truncate(20) }} — выражение с цепочкой фильтров!
+    fn parse_variable(&self, offset: usize, tag: &str) -> Result<TemplateToken, RenderError> {
+        let source = tag_body(tag).trim();
+        if source.is_empty() {
+            return Err(self.error_at(offset, tag, "пустая переменная — внутри {{ }} нужно имя!"));
+        }
+        let expr = parse_expr(source).map_err(|hint| self.error_at(offset, tag, &hint))?;
+        Ok(TemplateToken::Variable(expr))
+    }
+
+    // Разбираем {% ... %} — незнакомые теги больше не теряются в космосе!
+    fn parse_tag(&self, offset: usize, tag: &str) -> Result<TemplateToken, RenderError> {
+        let content = tag_body(tag).trim();
+        let (keyword, rest) = content.split_once(char::is_whitespace).unwrap_or((content, ""));
+        let rest = rest.trim();
+        let token = match keyword {
+            "for" => {
+                let Some((item_name, list_name)) = rest.split_once(" in ") else {
+                    return Err(self.error_at(offset, tag, "ожидаем {% for item in items %}"));
+                };
+                let (item_name, list_name) = (item_name.trim(), list_name.trim());
+                if !is_identifier(item_name) || list_name.is_empty() {
+                    return Err(self.error_at(offset, tag, "ожидаем {% for item in items %}"));
+                }
+                let list = parse_expr(list_name).map_err(|hint| self.error_at(offset, tag, &hint))?;
+                TemplateToken::ForStart(item_name.to_string(), list)
+            }
+            "if" | "elif" if rest.is_empty() => {
+                return Err(self.error_at(offset, tag, "условию нужно выражение!"));
+            }
+            "if" => TemplateToken::IfStart(parse_expr(rest).map_err(|hint| self.error_at(offset, tag, &hint))?),
+            "elif" => TemplateToken::ElseIf(parse_expr(rest).map_err(|hint| self.error_at(offset, tag, &hint))?),
+            "include" if rest.is_empty() => {
+                return Err(self.error_at(offset, tag, "какой шаблон включать? Укажите путь!"));
+            }
+            "include" => {
+                // Путь без кавычек — старый синтаксис {% include header.html %}, он тоже летает!
+                let (name, options) = match rest.starts_with(['"', '\'', '[']) {
+                    true => (None, rest),
+                    false => rest.split_once(char::is_whitespace).map_or((Some(rest), ""), |(name, options)| (Some(name), options)),
+                };
+                TemplateToken::Include(parse_include(name, options).map_err(|hint| self.error_at(offset, tag, &hint))?)
+            }
+            "extends" if rest.is_empty() => {
+                return Err(self.error_at(offset, tag, "от какого шаблона наследуемся? Укажите путь!"));
+            }
+            "extends" => TemplateToken::Extends(unquote(rest).to_string()),
+            "block" if !is_identifier(rest) => {
+                return Err(self.error_at(offset, tag, "ожидаем {% block name %}"));
+            }
+            "block" => TemplateToken::BlockStart(rest.to_string()),
+            "endblock" if rest.is_empty() => TemplateToken::BlockEnd(None),
+            "endblock" if is_identifier(rest) => TemplateToken::BlockEnd(Some(rest.to_string())),
+            "endblock" => return Err(self.error_at(offset, tag, "ожидаем {% endblock %} или {% endblock name %}")),
+            "macro" => {
+                let (name, params) = parse_signature(rest).map_err(|hint| self.error_at(offset, tag, &hint))?;
+                TemplateToken::MacroStart(name, params)
+            }
+            "import" => {
+                let Some((path, alias)) = rest.rsplit_once(" as ") else {
+                    return Err(self.error_at(offset, tag, "ожидаем {% import \"components.html\" as ui %}"));
+                };
+                let (path, alias) = (unquote(path.trim()), alias.trim());
+                if path.is_empty() || !is_identifier(alias) {
+                    return Err(self.error_at(offset, tag, "ожидаем {% import \"components.html\" as ui %}"));
+                }
+                TemplateToken::Import(path.to_string(), alias.to_string())
+            }
+            "set" if is_identifier(rest) => TemplateToken::SetBlockStart(rest.to_string()),
+            "set" => TemplateToken::Set(
+                parse_assignments(rest).map_err(|hint| self.error_at(offset, tag, &format!("{} — ожидаем {{% set name = value %}}", hint)))?,
+            ),
+            "with" if rest.is_empty() => TemplateToken::WithStart(Kwargs::new()),
+            "with" => TemplateToken::WithStart(
+                parse_assignments(rest).map_err(|hint| self.error_at(offset, tag, &format!("{} — ожидаем {{% with name = value %}}", hint)))?,
+            ),
+            "autoescape" => match rest {
+                "true" => TemplateToken::AutoEscapeStart(true),
+                "false" => TemplateToken::AutoEscapeStart(false),
+                _ => return Err(self.error_at(offset, tag, "ожидаем {% autoescape true %} или {% autoescape false %}")),
+            },
+            "endfor" | "else" | "endif" | "endautoescape" | "endmacro" | "endset" | "endwith" if !rest.is_empty() => {
+                return Err(self.error_at(offset, tag, "у этого тега не бывает аргументов!"));
+            }
+            "endfor" => TemplateToken::ForEnd,
+            "else" => TemplateToken::Else,
+            "endif" => TemplateToken::IfEnd,
+            "endautoescape" => TemplateToken::AutoEscapeEnd,
+            "endraw" => return Err(self.error_at(offset, tag, "{% endraw %} без {% raw %} — лишний шлюз!")),
+            "endmacro" => TemplateToken::MacroEnd,
+            "endset" => TemplateToken::SetEnd,
+            "endwith" => TemplateToken::WithEnd,
+            _ => {
+                let hint = format!("неизвестный тег '{}' — знаем {}", keyword, KNOWN_TAGS.join(", "));
+                return Err(self.error_at(offset, tag, &hint));
+            }
+        };
+        Ok(token)
+    }
+
+    // Собираем узлы до закрывающего токена — возвращаем, на чём остановились!
+    fn parse_block(&mut self) -> Result<(Vec<Node>, Option<Spanned>), RenderError> {
+        self.depth += 1;
+        let result = self.parse_nodes();
+        self.depth -= 1;
+        result
+    }
+
+    // Сам разбор узлов одного уровня!
+    fn parse_nodes(&mut self) -> Result<(Vec<Node>, Option<Spanned>), RenderError> {
+        let mut nodes = Vec::new();
+        while let Some(spanned) = self.tokens.next() {
+            let Spanned { token, offset, tag } = spanned;
+            match token {
+                TemplateToken::Text(text) => nodes.push(Node::Text(text)),
+                TemplateToken::Variable(expr) => nodes.push(Node::Variable(expr)),
+                TemplateToken::Include(spec) => nodes.push(Node::Include(spec)),
+                TemplateToken::Set(assignments) => nodes.push(Node::Set(assignments)),
+                TemplateToken::SetBlockStart(name) => {
+                    let (body, end) = self.parse_block()?; // Тело до {% endset %}!
+                    match end {
+                        Some(Spanned { token: TemplateToken::SetEnd, .. }) => {}
+                        Some(end) => return Err(self.error(&end, &format!("ожидали {{% endset %}} для '{}'!", name))),
+                        None => return Err(self.error_at(offset, &tag, "захват не закрыт — нужен {% endset %}!")),
+                    }
+                    nodes.push(Node::SetBlock { name, body });
+                }
+                TemplateToken::WithStart(assignments) => {
+                    let (body, end) = self.parse_block()?; // Тело до {% endwith %}!
+                    match end {
+                        Some(Spanned { token: TemplateToken::WithEnd, .. }) => {}
+                        Some(end) => return Err(self.error(&end, "ожидали {% endwith %} — блок ещё открыт!")),
+                        None => return Err(self.error_at(offset, &tag, "блок не закрыт — нужен {% endwith %}!")),
+                    }
+                    nodes.push(Node::With { assignments, body });
+                }
+                TemplateToken::ForStart(item, list) => {
+                    let (body, mut end) = self.parse_block()?; // Тело до {% else %} или {% endfor %}!
+                    let mut else_body = Vec::new();
+                    if let Some(Spanned { token: TemplateToken::Else, .. }) = end {
+                        (else_body, end) = self.parse_block()?; // Ветка для пустого списка!
+                    }
+                    match end {
+                        Some(Spanned { token: TemplateToken::ForEnd, .. }) => {}
+                        Some(end) => return Err(self.error(&end, "ожидали {% endfor %} — цикл ещё открыт!")),
+                        None => return Err(self.error_at(offset, &tag, "цикл не закрыт — нужен {% endfor %}!")),
+                    }
+                    nodes.push(Node::For { item, list, body, else_body });
+                }
+                TemplateToken::IfStart(condition) => nodes.push(self.parse_if(condition, offset, &tag)?),
+                TemplateToken::AutoEscapeStart(enabled) => {
+                    let (body, end) = self.parse_block()?; // Тело до {% endautoescape %}!
+                    match end {
+                        Some(Spanned { token: TemplateToken::AutoEscapeEnd, .. }) => {}
+                        Some(end) => return Err(self.error(&end, "ожидали {% endautoescape %} — блок ещё открыт!")),
+                        None => return Err(self.error_at(offset, &tag, "блок не закрыт — нужен {% endautoescape %}!")),
+                    }
+                    nodes.push(Node::AutoEscape { enabled, body });
+                }
+                TemplateToken::Extends(parent) => {
+                    if self.depth > 1 {
+                        return Err(self.error_at(offset, &tag, "{% extends %} живёт только на верхнем уровне шаблона!"));
+                    }
+                    if self.extends {
+                        return Err(self.error_at(offset, &tag, "второй {% extends %} — у шаблона один родитель!"));
+                    }
+                    self.extends = true;
+                    nodes.push(Node::Extends(parent));
+                }
+                TemplateToken::BlockStart(name) => {
+                    if !self.blocks.insert(name.clone()) {
+                        return Err(self.error_at(offset, &tag, &format!("блок '{}' уже объявлен в этом шаблоне!", name)));
+                    }
+                    let (body, end) = self.parse_block()?; // Тело до {% endblock %}!
+                    match end {
+                        Some(Spanned { token: TemplateToken::BlockEnd(None), .. }) => {}
+                        Some(Spanned { token: TemplateToken::BlockEnd(Some(ref end_name)), .. }) if *end_name == name => {}
+                        Some(end) => return Err(self.error(&end, &format!("ожидали {{% endblock %}} для блока '{}'!", name))),
+                        None => return Err(self.error_at(offset, &tag, "блок не закрыт — нужен {% endblock %}!")),
+                    }
+                    nodes.push(Node::Block { name, body: Arc::new(body) });
+                }
+                TemplateToken::MacroStart(name, params) => {
+                    if self.depth > 1 {
+                        return Err(self.error_at(offset, &tag, "{% macro %} объявляется только на верхнем уровне шаблона!"));
+                    }
+                    let (body, end) = self.parse_block()?; // Тело до {% endmacro %}!
+                    match end {
+                        Some(Spanned { token: TemplateToken::MacroEnd, .. }) => {}
+                        Some(end) => return Err(self.error(&end, &format!("ожидали {{% endmacro %}} для макроса '{}'!", name))),
+                        None => return Err(self.error_at(offset, &tag, "макрос не закрыт — нужен {% endmacro %}!")),
+                    }
+                    nodes.push(Node::Macro(Arc::new(Macro { name, params, body })));
+                }
+                TemplateToken::Import(path, alias) => {
+                    if self.depth > 1 {
+                        return Err(self.error_at(offset, &tag, "{% import %} живёт только на верхнем уровне шаблона!"));
+                    }
+                    nodes.push(Node::Import { path, alias });
+                }
+                end @ (TemplateToken::ForEnd
+                | TemplateToken::IfEnd
+                | TemplateToken::Else
+                | TemplateToken::ElseIf(_)
+                | TemplateToken::AutoEscapeEnd
+                | TemplateToken::BlockEnd(_)
+                | TemplateToken::MacroEnd
+                | TemplateToken::SetEnd
+                | TemplateToken::WithEnd) => {
+                    return Ok((nodes, Some(Spanned { token: end, offset, tag }))); // Закрывающий токен — пусть разбирается родитель!
+                }
+            }
+        }
+        Ok((nodes, None))
+    }
+
+    // Собираем цепочку if/elif/else — все маршруты условия!
+    fn parse_if(&mut self, condition: Expr, offset: usize, tag: &str) -> Result<Node, RenderError> {
+        let mut branches = Vec::new();
+        let mut condition = condition;
+        loop {
+            let (body, end) = self.parse_block()?;
+            branches.push((condition, body));
+            match end {
+                Some(Spanned { token: TemplateToken::ElseIf(next), .. }) => condition = next, // Следующий маршрут!
+                Some(Spanned { token: TemplateToken::Else, .. }) => {
+                    let (else_body, end) = self.parse_block()?; // Ветка else до {% endif %}!
+                    return match end {
+                        Some(Spanned { token: TemplateToken::IfEnd, .. }) => Ok(Node::If { branches, else_body }),
+                        Some(end) => Err(self.error(&end, "после {% else %} ждём только {% endif %}!")),
+                        None => Err(self.error_at(offset, tag, "условие не закрыто — нужен {% endif %}!")),
+                    };
+                }
+                Some(Spanned { token: TemplateToken::IfEnd, .. }) => return Ok(Node::If { branches, else_body: Vec::new() }),
+                Some(end) => return Err(self.error(&end, "ожидали {% endif %} — условие ещё открыто!")),
+                None => return Err(self.error_at(offset, tag, "условие не закрыто — нужен {% endif %}!")),
+            }
+        }
+    }
+
+    // Ошибка на токене — с координатами на карте!
+    fn error(&self, spanned: &Spanned, hint: &str) -> RenderError {
+        self.error_at(spanned.offset, &spanned.tag, hint)
+    }
+
+    // Ошибка по смещению — считаем строку и колонку!
+    fn error_at(&self, offset: usize, tag: &str, hint: &str) -> RenderError {
+        let (line, column) = self.position(offset);
+        RenderError::TemplateSyntax {
+            path: self.path.to_string(),
+            line,
+            column,
+            tag: tag.to_string(),
+            hint: hint.to_string(),
+        }
+    }
+
+    // Строка и колонка по смещению в исходнике — с единицы!
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let before = &self.source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        (line, column)
+    }
+
+    // Пришиваем к выражениям тега его координаты — strict-режим скажет, где переменная потерялась!
+    fn locate(&self, offset: usize, token: TemplateToken) -> TemplateToken {
+        let (line, column) = self.position(offset);
+        let location = Arc::new(Location { path: self.path.to_string(), line, column });
+        let at = |expr: Expr| Expr::Located(Box::new(expr), location.clone());
+        let all = |kwargs: Kwargs| kwargs.into_iter().map(|(name, expr)| (name, at(expr))).collect();
+        match token {
+            TemplateToken::Variable(expr) => TemplateToken::Variable(at(expr)),
+            TemplateToken::ForStart(item, list) => TemplateToken::ForStart(item, at(list)),
+            TemplateToken::IfStart(cond) => TemplateToken::IfStart(at(cond)),
+            TemplateToken::ElseIf(cond) => TemplateToken::ElseIf(at(cond)),
+            TemplateToken::Include(mut spec) => {
+                spec.context = spec.context.map(at);
+                TemplateToken::Include(spec)
+            }
+            TemplateToken::MacroStart(name, params) => {
+                TemplateToken::MacroStart(name, params.into_iter().map(|(param, default)| (param, default.map(at))).collect())
+            }
+            TemplateToken::Set(assignments) => TemplateToken::Set(all(assignments)),
+            TemplateToken::WithStart(assignments) => TemplateToken::WithStart(all(assignments)),
+            other => other,
+        }
+    }
+}
+
+// Содержимое тега без скобок и маркеров {%- -%} и {%+!
+fn tag_body(tag: &str) -> &str {
+    let body = &tag[2..tag.len() - 2];
+    let body = body.strip_prefix(['-', '+']).unwrap_or(body);
+    body.strip_suffix('-').unwrap_or(body)
+}
+
+// Срезаем пробелы с краёв текста — слева по предыдущему тегу, справа по маркеру {%-!
+fn trim_text(text: &str, trim_next: TrimNext, trim_end: bool) -> &str {
+    let text = match trim_next {
+        TrimNext::Nothing => text,
+        TrimNext::Whitespace => text.trim_start(),
+        TrimNext::FirstNewline => text.strip_prefix("\r\n").or_else(|| text.strip_prefix('\n')).unwrap_or(text),
+    };
+    if trim_end { text.trim_end() } else { text }
+}
+
+// Снимаем кавычки с пути: "base.html" → base.html!
+fn unquote(path: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = path.strip_prefix(quote).and_then(|p| p.strip_suffix(quote)) {
+            return inner;
+        }
+    }
+    path
+}
+
+// Подпись макроса: card(title, size=2) — имя и параметры с умолчаниями!
+fn parse_signature(signature: &str) -> Result<(String, Vec<Param>), String> {
+    let expected = "ожидаем {% macro name(arg, other=\"умолчание\") %}".to_string();
+    let (name, args, kwargs) = match parse_expr(signature)? {
+        Expr::Var(name) => (name, Vec::new(), Vec::new()), // Макрос без параметров — скобки не обязательны!
+        Expr::Call { name, args, kwargs } => (name, args, kwargs),
+        _ => return Err(expected),
+    };
+    if !is_identifier(&name) {
+        return Err(expected);
+    }
+    let mut params: Vec<Param> = Vec::new();
+    for arg in args {
+        match arg {
+            Expr::Var(param) if is_identifier(&param) => params.push((param, None)),
+            _ => return Err(format!("параметр макроса '{}' должен быть именем", name)),
+        }
+    }
+    params.extend(kwargs.into_iter().map(|(param, default)| (param, Some(default))));
+    for (i, (param, _)) in params.iter().enumerate() {
+        if params[..i].iter().any(|(other, _)| other == param) {
+            return Err(format!("параметр '{}' объявлен в макросе '{}' дважды", param, name));
+        }
+    }
+    Ok((name, params))
+}
+
+// Годится ли строка в имя переменной?
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Vec<Node>, RenderError> {
+        parse_template("t.html", source, Whitespace::default())
+    }
+
+    // Где споткнулся парсер — строка, колонка и подсказка!
+    fn syntax_error(source: &str) -> (usize, usize, String) {
+        match parse(source) {
+            Err(RenderError::TemplateSyntax { line, column, hint, .. }) => (line, column, hint),
+            other => panic!("ждали TemplateSyntax, а получили {:?}", other.map(|nodes| nodes.len())),
+        }
+    }
+
+    #[test]
+    fn nested_for_in_if_in_for() {
+        let nodes = parse("{% for row in rows %}{% if row.tags %}{% for tag in row.tags %}{{ tag }}{% endfor %}{% else %}-{% endif %}{% endfor %}").unwrap();
+        let [Node::For { item, body, else_body, .. }] = nodes.as_slice() else { panic!("ждали один цикл") };
+        assert_eq!(item, "row");
+        assert!(else_body.is_empty());
+        let [Node::If { branches, else_body }] = body.as_slice() else { panic!("ждали условие в цикле") };
+        assert!(matches!(else_body.as_slice(), [Node::Text(text)] if text == "-"));
+        let [(_, then)] = branches.as_slice() else { panic!("ждали одну ветку") };
+        let [Node::For { item, body, .. }] = then.as_slice() else { panic!("ждали цикл в условии") };
+        assert_eq!(item, "tag");
+        assert!(matches!(body.as_slice(), [Node::Variable(_)]));
+    }
+
+    #[test]
+    fn reports_line_and_column() {
+        assert_eq!(syntax_error("a\nb {% if x %}c").0, 2);
+        let (line, column, _) = syntax_error("строка\n  {% endfor %}");
+        assert_eq!((line, column), (2, 3));
+        let (line, column, hint) = syntax_error("ok\n\n{{ x }");
+        assert_eq!((line, column), (3, 1));
+        assert!(hint.contains("}}"));
+        assert!(syntax_error("{% endif }").2.contains("%}"));
+        assert!(syntax_error("{# не закрыт").2.contains("#}"));
+        assert!(syntax_error("{% fro x in y %}").2.contains("for")); // Подсказка по знакомым тегам!
+    }
+
+    #[test]
+    fn braces_inside_strings_do_not_close_tags() {
+        let nodes = parse("{{ '}}' }}{% set x = \"%}\" %}{# it's #}").unwrap();
+        assert!(matches!(nodes.as_slice(), [Node::Variable(_), Node::Set(_)]));
+    }
+
+    #[test]
+    fn whitespace_markers_and_raw() {
+        let nodes = parse("a  {{- 1 -}}  b{% raw %}{{ x }{% endraw %}").unwrap();
+        let texts: Vec<&str> = nodes.iter().filter_map(|node| if let Node::Text(text) = node { Some(text.as_str()) } else { None }).collect();
+        assert_eq!(texts, ["a", "b", "{{ x }"]);
+    }
+}
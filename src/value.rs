@@ -0,0 +1,270 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::filters::format_datetime;
+
+// Значение в шаблоне — звёздная материя, из которой строятся страницы!
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,                         // Пустота космоса — значения нет!
+    Bool(bool),                   // Да или нет — маяк горит или погас!
+    Int(i64),                     // Целое число — счётчик звёзд!
+    Float(f64),                   // Дробное число — скорость звездолёта!
+    String(String),               // Строка — обычный груз, экранируется при выводе!
+    Safe(String),                 // Доверенная строка — летит в шаблон без экранирования!
+    List(Vec<Value>),             // Список — флотилия значений!
+    Map(BTreeMap<String, Value>), // Словарь — строка данных с полями!
+    Bytes(Vec<u8>),               // Сырые байты — картинки и вложения, в тексте летят base64!
+    DateTime(SystemTime),         // Момент времени — в тексте летит ISO 8601 в UTC!
+}
+
+impl Value {
+    // Доверенная строка — для HTML, который вы проверили сами!
+    pub fn safe(value: impl Into<String>) -> Self {
+        Value::Safe(value.into())
+    }
+
+    // Сырые байты — для вложений и бинарных полей!
+    pub fn bytes(value: impl Into<Vec<u8>>) -> Self {
+        Value::Bytes(value.into())
+    }
+
+    // Значение из строки базы — "101", "0.9" и "true" становятся числами и логикой!
+    // Приводим только без потерь: "007" и "1.50" остаются строками, иначе изменится вывод.
+    pub fn infer(value: &str) -> Self {
+        if value == "true" || value == "false" {
+            return Value::Bool(value == "true");
+        }
+        if let Some(int) = value.parse::<i64>().ok().filter(|int| int.to_string() == value) {
+            return Value::Int(int);
+        }
+        if let Some(float) = value.parse::<f64>().ok().filter(|float| float.is_finite() && value.contains('.') && float.to_string() == value) {
+            return Value::Float(float);
+        }
+        Value::String(value.to_string())
+    }
+
+    // Строковое содержимое — если это строка!
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) | Value::Safe(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    // Пусто ли в трюме? Null, пустая строка, пустой список или словарь!
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Value::Null => true,
+            Value::String(s) | Value::Safe(s) => s.is_empty(),
+            Value::List(items) => items.is_empty(),
+            Value::Map(fields) => fields.is_empty(),
+            Value::Bytes(bytes) => bytes.is_empty(),
+            Value::Bool(_) | Value::Int(_) | Value::Float(_) | Value::DateTime(_) => false,
+        }
+    }
+
+    // Истинно ли значение в {% if %}? Пустое, ноль, false и строка "false" — ложь!
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Int(i) => *i != 0,
+            Value::Float(f) => *f != 0.0,
+            Value::String(s) | Value::Safe(s) => !s.is_empty() && s != "false", // Из yuaidb всё летит строками!
+            other => !other.is_empty(),
+        }
+    }
+
+    // Число из значения — строки тоже пробуем распарсить!
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            Value::String(s) | Value::Safe(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    // Равенство с приведением — "0.9" из базы равно числу 0.9!
+    pub(crate) fn loose_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Null, _) | (_, Value::Null) => false,
+            (Value::List(a), Value::List(b)) => a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.loose_eq(y)),
+            (Value::Map(a), Value::Map(b)) => a.len() == b.len() && a.iter().all(|(k, x)| b.get(k).is_some_and(|y| x.loose_eq(y))),
+            _ => match (self.as_f64(), other.as_f64()) {
+                (Some(a), Some(b)) => a == b,
+                _ => self.to_string() == other.to_string(),
+            },
+        }
+    }
+
+    // Порядок с приведением — числа сравниваем как числа, остальное как строки!
+    pub(crate) fn loose_cmp(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => None,
+            (Value::List(_) | Value::Map(_), _) | (_, Value::List(_) | Value::Map(_)) => None,
+            (Value::DateTime(a), Value::DateTime(b)) => Some(a.cmp(b)),
+            _ => match (self.as_f64(), other.as_f64()) {
+                (Some(a), Some(b)) => a.partial_cmp(&b),
+                _ => Some(self.to_string().cmp(&other.to_string())),
+            },
+        }
+    }
+
+    // Переводим в JSON — для фильтра json и машинных орбит!
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(b) => serde_json::Value::Bool(*b),
+            Value::Int(i) => serde_json::Value::from(*i),
+            Value::Float(f) => serde_json::Value::from(*f),
+            Value::String(s) | Value::Safe(s) => serde_json::Value::from(s.as_str()),
+            Value::List(items) => serde_json::Value::Array(items.iter().map(Value::to_json).collect()),
+            Value::Map(fields) => serde_json::Value::Object(fields.iter().map(|(k, v)| (k.clone(), v.to_json())).collect()),
+            Value::Bytes(_) | Value::DateTime(_) => serde_json::Value::from(self.to_string()), // base64 и ISO 8601!
+        }
+    }
+}
+
+// Секунды от начала эпохи — и до 1970 года тоже, отрицательными!
+pub(crate) fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs_f64().ceil() as i64),
+    }
+}
+
+// Выводим значение в шаблон — списки через запятую!
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::String(s) | Value::Safe(s) => f.write_str(s),
+            Value::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+            Value::Map(fields) => {
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                Ok(())
+            }
+            Value::Bytes(bytes) => f.write_str(&base64::encode(bytes)),
+            Value::DateTime(time) => f.write_str(&format_datetime(*time, "%Y-%m-%dT%H:%M:%SZ")),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Value::Float(value.into())
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(value: &[u8]) -> Self {
+        Value::Bytes(value.to_vec())
+    }
+}
+
+impl From<SystemTime> for Value {
+    fn from(value: SystemTime) -> Self {
+        Value::DateTime(value)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+// Строка из yuaidb — словарь, где числа и логика снова становятся собой!
+impl From<HashMap<String, String>> for Value {
+    fn from(row: HashMap<String, String>) -> Self {
+        Value::Map(row.into_iter().map(|(k, v)| (k, Value::infer(&v))).collect())
+    }
+}
+
+
+impl<T: Into<Value>> From<BTreeMap<String, T>> for Value {
+    fn from(fields: BTreeMap<String, T>) -> Self {
+        Value::Map(fields.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+// JSON с чужой орбиты — числа остаются числами!
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(b),
+            serde_json::Value::Number(n) => n.as_i64().map(Value::Int).unwrap_or_else(|| Value::Float(n.as_f64().unwrap_or(0.0))),
+            serde_json::Value::String(s) => Value::String(s),
+            serde_json::Value::Array(items) => Value::List(items.into_iter().map(Into::into).collect()),
+            serde_json::Value::Object(fields) => Value::Map(fields.into_iter().map(|(k, v)| (k, v.into())).collect()),
+        }
+    }
+}